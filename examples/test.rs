@@ -23,7 +23,7 @@ fn apply() {
 
 fn ns_test() {
     let ns1 = NetNS::get();
-    if !ns1.is_ok() {
+    if ns1.is_err() {
         panic!("get failed {:?}", ns1);
     }
 
@@ -78,7 +78,7 @@ fn ns_test() {
     }
 
     let ns5 = NetNS::get_from_process(26671);
-    if !ns5.is_ok() {
+    if ns5.is_err() {
         println!("get failed {}", ns5.err().unwrap());
         return
     }
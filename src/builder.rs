@@ -0,0 +1,83 @@
+use super::errno;
+use super::netns_linux::{on_helper_thread, NetNS};
+use super::profile::SysctlProfile;
+use super::rtnl::Rtnl;
+
+/// Configures how a new network namespace is set up right after it is
+/// unshared. By default `lo` is brought up so the namespace is usable for
+/// localhost traffic straight away.
+#[derive(Clone, Debug)]
+pub struct NetNSBuilder {
+    loopback: bool,
+    sysctl: Option<SysctlProfile>,
+}
+
+impl Default for NetNSBuilder {
+    fn default() -> NetNSBuilder {
+        NetNSBuilder {
+            loopback: true,
+            sysctl: None,
+        }
+    }
+}
+
+impl NetNSBuilder {
+    pub fn new() -> NetNSBuilder {
+        NetNSBuilder::default()
+    }
+
+    /// Whether to bring `lo` up in the new namespace.
+    pub fn loopback(mut self, up: bool) -> NetNSBuilder {
+        self.loopback = up;
+        self
+    }
+
+    /// A sysctl profile applied in the new namespace, see
+    /// `SysctlProfile::apply`.
+    pub fn sysctl(mut self, profile: SysctlProfile) -> NetNSBuilder {
+        self.sysctl = Some(profile);
+        self
+    }
+
+    fn setup(&self) -> errno::Result<()> {
+        if self.loopback {
+            let mut rtnl = Rtnl::new()?;
+            let lo = rtnl.link_index("lo")?;
+            rtnl.link_set_up(lo)?;
+        }
+        if let Some(ref profile) = self.sysctl {
+            profile.apply()?;
+        }
+        Ok(())
+    }
+
+    /// Creates the namespace and moves the calling thread into it, like
+    /// `NetNS::new`. On failure the thread is moved back to its previous
+    /// namespace.
+    pub fn build(&self) -> errno::Result<NetNS> {
+        let prev = NetNS::get()?;
+        let ns = NetNS::new()?;
+        if let Err(e) = self.setup() {
+            NetNS::set(&prev)?;
+            return Err(e);
+        }
+        Ok(ns)
+    }
+
+    /// Like `build`, but the calling thread stays in its current
+    /// namespace.
+    pub fn build_detached(&self) -> errno::Result<NetNS> {
+        on_helper_thread(|| {
+            let ns = NetNS::new()?;
+            self.setup()?;
+            Ok(ns)
+        })
+        .map(NetNS::into_fd_path)
+    }
+}
+
+impl NetNS {
+    pub fn builder() -> NetNSBuilder {
+        NetNSBuilder::new()
+    }
+}
@@ -0,0 +1,169 @@
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt as _;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use super::errno::{self, Errno, Error};
+use super::mountns::{cstr, remount_sysfs};
+use super::namespace::{Namespace, NsType};
+use super::netns_linux::{mount, setns, unshare, NetNS, CLONE_NEWNET, CLONE_NEWNS};
+
+/// Per-namespace files bind mounted over `/etc` by `netns_exec`, as used
+/// by `ip netns exec`.
+pub const NETNS_ETC_DIR: &str = "/etc/netns";
+
+/// Extends `std::process::Command` with namespaces the child joins after
+/// fork and before exec, so the parent never changes namespace.
+///
+/// ```no_run
+/// use std::process::Command;
+/// use netns::{CommandExt, NetNS};
+///
+/// let ns = NetNS::get_from_path("/run/netns/blue")?;
+/// let out = Command::new("ip").arg("addr").netns(&ns).output()?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub trait CommandExt {
+    /// Runs the child in the network namespace `ns`.
+    fn netns(&mut self, ns: &NetNS) -> &mut Command;
+
+    /// Runs the child in all of `namespaces`. If a user namespace is among
+    /// them, namespaces that cannot be joined before it are retried after
+    /// it, like nsenter(1). Joining a PID namespace only affects the
+    /// children of the spawned process.
+    fn namespaces<'a, I>(&mut self, namespaces: I) -> &mut Command
+    where
+        I: IntoIterator<Item = &'a Namespace>;
+
+    /// Runs the child like `ip netns exec <name>`: it joins the namespace
+    /// at `/run/netns/<name>` and gets its own mount namespace in which
+    /// `/sys` describes that network namespace and every file in
+    /// `/etc/netns/<name>/` is bind mounted over its counterpart in `/etc`.
+    fn netns_exec(&mut self, name: &str) -> &mut Command;
+}
+
+impl CommandExt for Command {
+    fn netns(&mut self, ns: &NetNS) -> &mut Command {
+        join_in_child(self, ns.to_namespace().map(|ns| vec![ns]))
+    }
+
+    fn namespaces<'a, I>(&mut self, namespaces: I) -> &mut Command
+    where
+        I: IntoIterator<Item = &'a Namespace>,
+    {
+        let handles = namespaces.into_iter().map(|ns| ns.try_clone()).collect();
+        join_in_child(self, handles)
+    }
+
+    fn netns_exec(&mut self, name: &str) -> &mut Command {
+        let exec = NetNS::get_from_name(name).and_then(|ns| NetnsExec::new(name, ns));
+        let hook = move || match exec {
+            Ok(ref exec) => exec.enter(),
+            Err(e) => Err(e.into()),
+        };
+        unsafe { self.pre_exec(hook) }
+    }
+}
+
+// The handles are duplicated up front and moved into the pre_exec hook, so
+// they stay valid until spawn even if the caller drops its own. Errors from
+// duplicating them are reported by spawn. The hook itself only issues
+// setns(2) calls and does not allocate.
+fn join_in_child(cmd: &mut Command, handles: errno::Result<Vec<Namespace>>) -> &mut Command {
+    let mut retry = vec![false; handles.as_ref().map(|v| v.len()).unwrap_or(0)];
+    let hook = move || {
+        let handles = match handles {
+            Ok(ref v) => v,
+            Err(e) => return Err(e.into()),
+        };
+        let has_user = handles.iter().any(|ns| ns.ns_type() == NsType::User);
+        for (i, ns) in handles.iter().enumerate() {
+            retry[i] = false;
+            if ns.ns_type() == NsType::User {
+                continue;
+            }
+            if let Err(e) = Namespace::set(ns) {
+                if !has_user {
+                    return Err(e.into());
+                }
+                retry[i] = true;
+            }
+        }
+        for ns in handles.iter().filter(|ns| ns.ns_type() == NsType::User) {
+            Namespace::set(ns)?;
+        }
+        for (i, ns) in handles.iter().enumerate() {
+            if retry[i] {
+                Namespace::set(ns)?;
+            }
+        }
+        Ok(())
+    };
+    unsafe { cmd.pre_exec(hook) }
+}
+
+fn to_cstring(path: &Path) -> errno::Result<CString> {
+    CString::new(path.as_os_str().as_bytes()).map_err(|_| Error::Sys(Errno::from_i32(libc::EINVAL)))
+}
+
+// Everything `ip netns exec` does between fork and exec, with the paths
+// resolved beforehand so that `enter` does not allocate.
+struct NetnsExec {
+    ns: NetNS,
+    name: CString,
+    binds: Vec<(CString, CString)>,
+}
+
+impl NetnsExec {
+    fn new(name: &str, ns: NetNS) -> errno::Result<NetnsExec> {
+        let dir = Path::new(NETNS_ETC_DIR).join(name);
+        let mut files: Vec<PathBuf> = Vec::new();
+        match fs::read_dir(&dir) {
+            Ok(entries) => {
+                for entry in entries {
+                    files.push(entry?.file_name().into());
+                }
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        files.sort();
+
+        let mut binds = Vec::new();
+        for file in files.iter() {
+            binds.push((
+                to_cstring(&dir.join(file))?,
+                to_cstring(&Path::new("/etc").join(file))?,
+            ));
+        }
+        Ok(NetnsExec {
+            ns,
+            name: to_cstring(Path::new(name))?,
+            binds,
+        })
+    }
+
+    fn enter(&self) -> io::Result<()> {
+        setns(self.ns.fd(), CLONE_NEWNET)?;
+        unshare(CLONE_NEWNS)?;
+        // Keep our mounts from propagating back to the parent.
+        mount(
+            None,
+            cstr(b"/\0"),
+            None,
+            libc::MS_SLAVE | libc::MS_REC,
+            None,
+        )?;
+        // The sysfs source name is only cosmetic; ip uses the netns name.
+        remount_sysfs(&self.name)?;
+
+        // Like ip, a file that cannot be bind mounted is skipped.
+        for (src, dst) in self.binds.iter() {
+            let _ = mount(Some(src), dst, None, libc::MS_BIND, None);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,130 @@
+use std::fs;
+
+use super::errno::{self, Errno, Error};
+use super::netns_linux::NetNS;
+
+// Prefixes runtimes put in front of the container ID in systemd scope
+// names, e.g. `docker-<id>.scope`.
+const SCOPE_PREFIXES: [&str; 4] = ["docker-", "libpod-", "cri-containerd-", "crio-"];
+
+/// The processes of the container whose ID starts with `id`, found through
+/// the cgroup paths Docker, containerd, CRI-O and Podman place them in,
+/// sorted so the container's init usually comes first. Like the docker
+/// CLI, an unambiguous prefix of the 64 character ID is enough.
+///
+/// Fails with `ENOENT` if no running container matches and `ENOTUNIQ` if
+/// the prefix matches more than one container.
+pub fn find_container_pids(id: &str) -> errno::Result<Vec<i32>> {
+    if id.is_empty() || !id.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return Err(Error::Sys(Errno::from_i32(libc::EINVAL)));
+    }
+    let id = id.to_ascii_lowercase();
+    let mut found: Option<String> = None;
+    let mut pids = Vec::new();
+    for entry in fs::read_dir("/proc")?.flatten() {
+        let pid: i32 = match entry.file_name().to_str().and_then(|s| s.parse().ok()) {
+            Some(pid) => pid,
+            None => continue,
+        };
+        // The process may have exited since the directory was read.
+        let cgroup = match fs::read_to_string(format!("/proc/{}/cgroup", pid)) {
+            Ok(s) => s,
+            Err(_) => continue,
+        };
+        let full = cgroup
+            .lines()
+            .filter_map(|l| container_id(cgroup_path(l)?))
+            .find(|full| full.starts_with(&id));
+        if let Some(full) = full {
+            match found {
+                Some(ref f) if f != full => {
+                    return Err(Error::Sys(Errno::from_i32(libc::ENOTUNIQ)))
+                }
+                _ => found = Some(full.to_string()),
+            }
+            pids.push(pid);
+        }
+    }
+    if pids.is_empty() {
+        return Err(Error::Sys(Errno::from_i32(libc::ENOENT)));
+    }
+    pids.sort_unstable();
+    Ok(pids)
+}
+
+impl NetNS {
+    /// Opens the network namespace of a running container given its ID or
+    /// a unique prefix of it, see `find_container_pids`. No container
+    /// daemon is involved.
+    pub fn get_from_container(id: &str) -> errno::Result<NetNS> {
+        let mut res = Err(Error::Sys(Errno::from_i32(libc::ENOENT)));
+        for pid in find_container_pids(id)? {
+            res = NetNS::get_from_process(pid);
+            if res.is_ok() {
+                break;
+            }
+        }
+        res
+    }
+}
+
+// The path of a line from /proc/<pid>/cgroup, which looks like
+// `4:memory:/docker/<id>` for cgroup v1 and `0::/<path>` for v2.
+fn cgroup_path(line: &str) -> Option<&str> {
+    line.splitn(3, ':').nth(2)
+}
+
+// The container ID in a cgroup path, if any. IDs appear as a path
+// component of their own (`/docker/<id>`, `/kubepods/.../<id>`) or in a
+// systemd scope (`/system.slice/docker-<id>.scope`). The scopes of the
+// conmon monitors Podman and CRI-O run next to each container are skipped.
+fn container_id(path: &str) -> Option<&str> {
+    path.split('/').find_map(|c| {
+        let c = c.strip_suffix(".scope").unwrap_or(c);
+        let c = SCOPE_PREFIXES
+            .iter()
+            .find_map(|p| c.strip_prefix(p))
+            .unwrap_or(c);
+        if c.len() == 64 && c.bytes().all(|b| b.is_ascii_hexdigit()) {
+            Some(c)
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "3f2b1c9e8d7a6b5c4d3e2f1a0b9c8d7e6f5a4b3c2d1e0f9a8b7c6d5e4f3a2b1c";
+
+    #[test]
+    fn ids() {
+        let paths = [
+            format!("/docker/{}", ID),
+            format!("/system.slice/docker-{}.scope", ID),
+            format!("/kubepods/burstable/pod1234/{}", ID),
+            format!(
+                "/kubepods.slice/kubepods-pod1234.slice/cri-containerd-{}.scope",
+                ID
+            ),
+            format!("/machine.slice/libpod-{}.scope/container", ID),
+            format!("/kubepods.slice/crio-{}.scope", ID),
+            format!("/default/{}", ID),
+        ];
+        for p in paths.iter() {
+            assert_eq!(container_id(p), Some(ID), "{}", p);
+        }
+        assert_eq!(
+            container_id(&format!("/machine.slice/libpod-conmon-{}.scope", ID)),
+            None
+        );
+        assert_eq!(
+            container_id("/user.slice/user-1000.slice/session-2.scope"),
+            None
+        );
+        assert_eq!(cgroup_path("12:memory:/docker/a:b"), Some("/docker/a:b"));
+        assert_eq!(cgroup_path("0::/"), Some("/"));
+    }
+}
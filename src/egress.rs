@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use super::errno::{self, Errno, Error};
+use super::netns_linux::{fstat, getpid, NetNS};
+use super::nftables::{Batch, Chain, ChainType, Family, Hook, Nftables, Rule, Table};
+use super::rtnl::Rtnl;
+use super::sysctl::{self, SysctlValue};
+
+const IP_FORWARD: &str = "net.ipv4.ip_forward";
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+// Per host namespace, keyed by its device and inode: the number of live
+// egress handles relying on ip_forward there, and the value it had before
+// the first one enabled it.
+type ForwardState = HashMap<(u64, u64), (usize, Option<SysctlValue>)>;
+static FORWARD: Mutex<Option<ForwardState>> = Mutex::new(None);
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EgressConfig {
+    /// Name of the host end of the veth pair, generated when `None`.
+    pub host_ifname: Option<String>,
+    /// Name of the veth end inside the namespace.
+    pub ns_ifname: String,
+    /// Network address of the /30 shared by both ends. The host end gets
+    /// the first address and the namespace end the second one.
+    pub subnet: Ipv4Addr,
+}
+
+impl Default for EgressConfig {
+    fn default() -> EgressConfig {
+        EgressConfig {
+            host_ifname: None,
+            ns_ifname: "eth0".to_string(),
+            subnet: Ipv4Addr::new(10, 200, 0, 0),
+        }
+    }
+}
+
+/// A network namespace with outbound connectivity through the host, as
+/// created by `NetNS::with_egress`. Dropping it removes the veth pair, the
+/// masquerade rules and restores `ip_forward`.
+#[derive(Debug)]
+pub struct Egress {
+    ns: NetNS,
+    host: NetNS,
+    host_ifname: String,
+    host_addr: Ipv4Addr,
+    ns_addr: Ipv4Addr,
+    veth: bool,
+    forward: bool,
+    table: Option<Table>,
+}
+
+impl Egress {
+    pub fn ns(&self) -> &NetNS {
+        &self.ns
+    }
+
+    pub fn host_ifname(&self) -> &str {
+        &self.host_ifname
+    }
+
+    pub fn host_addr(&self) -> Ipv4Addr {
+        self.host_addr
+    }
+
+    pub fn ns_addr(&self) -> Ipv4Addr {
+        self.ns_addr
+    }
+
+    fn setup(&mut self, config: &EgressConfig) -> errno::Result<()> {
+        let mut host = Rtnl::new_in(&self.host)?;
+        host.link_add_veth(&self.host_ifname, &config.ns_ifname, Some(&self.ns))?;
+        self.veth = true;
+        let index = host.link_index(&self.host_ifname)?;
+        host.addr_add(index, IpAddr::V4(self.host_addr), 30)?;
+        host.link_set_up(index)?;
+
+        let mut inner = Rtnl::new_in(&self.ns)?;
+        let index = inner.link_index(&config.ns_ifname)?;
+        inner.addr_add(index, IpAddr::V4(self.ns_addr), 30)?;
+        inner.link_set_up(index)?;
+        inner.route_add_default(IpAddr::V4(self.host_addr))?;
+
+        let key = ns_key(&self.host)?;
+        self.host.run(|| forward_acquire(key))?;
+        self.forward = true;
+
+        let table = Table::new(Family::Ip, &format!("netns_egress_{}", self.host_ifname));
+        let chain = Chain::new(Family::Ip, &table.name, "postrouting").hook(
+            ChainType::Nat,
+            Hook::Postrouting,
+            100,
+        );
+        let rule = Rule::new(Family::Ip, &table.name, &chain.name)
+            .saddr(IpAddr::V4(self.ns_addr), 30)
+            .masquerade();
+        let mut nft = Nftables::new_in(&self.host)?;
+        nft.apply(
+            Batch::new()
+                .add_table(&table)
+                .add_chain(&chain)
+                .add_rule(&rule),
+        )?;
+        self.table = Some(table);
+        Ok(())
+    }
+}
+
+impl Drop for Egress {
+    fn drop(&mut self) {
+        if let Some(table) = self.table.take() {
+            if let Ok(mut nft) = Nftables::new_in(&self.host) {
+                let _ = nft.delete_table(&table);
+            }
+        }
+        if self.veth {
+            if let Ok(mut rtnl) = Rtnl::new_in(&self.host) {
+                if let Ok(index) = rtnl.link_index(&self.host_ifname) {
+                    let _ = rtnl.link_del(index);
+                }
+            }
+        }
+        if self.forward {
+            if let Ok(key) = ns_key(&self.host) {
+                let _ = self.host.run(|| forward_release(key));
+            }
+        }
+    }
+}
+
+fn ns_key(ns: &NetNS) -> errno::Result<(u64, u64)> {
+    let st = fstat(ns.fd())?;
+    Ok((st.st_dev, st.st_ino))
+}
+
+fn forward_acquire(key: (u64, u64)) -> errno::Result<()> {
+    let mut forward = FORWARD.lock().unwrap_or_else(|e| e.into_inner());
+    let state = forward
+        .get_or_insert_with(HashMap::new)
+        .entry(key)
+        .or_insert((0, None));
+    if state.0 == 0 {
+        let old = sysctl::read(IP_FORWARD)?;
+        if old.as_bool() != Some(true) {
+            sysctl::write(IP_FORWARD, &SysctlValue::Int(1))?;
+            state.1 = Some(old);
+        }
+    }
+    state.0 += 1;
+    Ok(())
+}
+
+fn forward_release(key: (u64, u64)) -> errno::Result<()> {
+    let mut forward = FORWARD.lock().unwrap_or_else(|e| e.into_inner());
+    let map = forward.get_or_insert_with(HashMap::new);
+    let state = match map.get_mut(&key) {
+        Some(v) => v,
+        None => return Ok(()),
+    };
+    state.0 = state.0.saturating_sub(1);
+    if state.0 == 0 {
+        let old = map.remove(&key).and_then(|(_, old)| old);
+        if let Some(old) = old {
+            sysctl::write(IP_FORWARD, &old)?;
+        }
+    }
+    Ok(())
+}
+
+impl NetNS {
+    /// Creates a new network namespace connected to the calling thread's
+    /// namespace through a veth pair on a /30, with a default route, IPv4
+    /// forwarding and masquerading on the host side. Everything is torn
+    /// down again when the returned handle is dropped.
+    pub fn with_egress(config: &EgressConfig) -> errno::Result<Egress> {
+        let net = u32::from(config.subnet);
+        if net & 3 != 0 {
+            return Err(Error::Sys(Errno::from_i32(libc::EINVAL)));
+        }
+        let host_ifname = match config.host_ifname {
+            Some(ref name) => name.clone(),
+            None => format!(
+                "veg{:x}x{:x}",
+                getpid(),
+                NEXT_ID.fetch_add(1, Ordering::Relaxed)
+            ),
+        };
+
+        let mut egress = Egress {
+            ns: NetNS::builder().build_detached()?,
+            host: NetNS::get()?,
+            host_ifname,
+            host_addr: Ipv4Addr::from(net + 1),
+            ns_addr: Ipv4Addr::from(net + 2),
+            veth: false,
+            forward: false,
+            table: None,
+        };
+        egress.setup(config)?;
+        Ok(egress)
+    }
+}
@@ -1,6 +1,6 @@
 use cfg_if::cfg_if;
 use libc::c_int;
-use std::{fmt, result};
+use std::{fmt, io, result};
 
 pub type Result<T> = result::Result<T, Error>;
 
@@ -8,6 +8,8 @@ pub type Result<T> = result::Result<T, Error>;
 pub enum Error {
     Sys(Errno),
     UnsupportedOperation,
+    /// A malformed line in a text format such as a sysctl profile.
+    Parse { line: usize },
 }
 
 impl Error {
@@ -22,10 +24,26 @@ impl Error {
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        if let Error::Sys(e) = self {
-            write!(f, "{:?}: {}", self, e.desc())
-        } else {
-            write!(f, "{:?}: {}", self, "UnsupportedOperation")
+        match self {
+            Error::Sys(e) => write!(f, "{:?}: {}", self, e.desc()),
+            Error::UnsupportedOperation => write!(f, "{:?}: UnsupportedOperation", self),
+            Error::Parse { line } => write!(f, "{:?}: invalid syntax on line {}", self, line),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Sys(Errno(e.raw_os_error().unwrap_or(libc::EIO)))
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(e: Error) -> io::Error {
+        match e {
+            Error::Sys(Errno(n)) => io::Error::from_raw_os_error(n),
+            Error::UnsupportedOperation => io::Error::from(io::ErrorKind::Unsupported),
+            Error::Parse { .. } => io::Error::from(io::ErrorKind::InvalidData),
         }
     }
 }
@@ -49,7 +67,7 @@ cfg_if! {
 }
 
 pub fn errno() -> i32 {
-    unsafe { (*errno_location()) as i32 }
+    unsafe { *errno_location() }
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -87,6 +105,12 @@ impl ErrnoBasis for i32 {
     }
 }
 
+impl ErrnoBasis for i64 {
+    fn basis() -> Self {
+        -1
+    }
+}
+
 fn last() -> Errno {
     Errno(errno())
 }
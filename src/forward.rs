@@ -0,0 +1,354 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::io;
+use std::net::{
+    IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream, UdpSocket,
+};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::errno;
+use super::netns_linux::{getpid, NetNS};
+use super::nftables::{Batch, Chain, ChainType, Family, Hook, Nftables, Protocol, Rule, Table};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+const UDP_IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+const UDP_BUF_SIZE: usize = 65536;
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Forwards a port of the calling thread's namespace to an address inside
+/// another namespace with DNAT rules. The target must be routable from the
+/// host, e.g. the namespace end of `NetNS::with_egress`. Connections from
+/// the host itself must use a non-loopback host address.
+#[derive(Debug)]
+pub struct DnatForward {
+    host: NetNS,
+    table: Table,
+}
+
+impl DnatForward {
+    pub fn new(proto: Protocol, port: u16, target: SocketAddr) -> errno::Result<DnatForward> {
+        let family = match target {
+            SocketAddr::V4(_) => Family::Ip,
+            SocketAddr::V6(_) => Family::Ip6,
+        };
+        let table = Table::new(
+            family,
+            &format!(
+                "netns_fwd_{:x}_{:x}",
+                getpid(),
+                NEXT_ID.fetch_add(1, Ordering::Relaxed)
+            ),
+        );
+        let mut batch = Batch::new();
+        batch.add_table(&table);
+        for (name, hook) in [("prerouting", Hook::Prerouting), ("output", Hook::Output)] {
+            let chain = Chain::new(family, &table.name, name).hook(ChainType::Nat, hook, -100);
+            let rule = Rule::new(family, &table.name, name)
+                .daddr_local()
+                .dport(proto, port)
+                .dnat(target.ip(), Some(target.port()));
+            batch.add_chain(&chain).add_rule(&rule);
+        }
+
+        let host = NetNS::get()?;
+        Nftables::new_in(&host)?.apply(&batch)?;
+        Ok(DnatForward { host, table })
+    }
+}
+
+impl Drop for DnatForward {
+    fn drop(&mut self) {
+        if let Ok(mut nft) = Nftables::new_in(&self.host) {
+            let _ = nft.delete_table(&self.table);
+        }
+    }
+}
+
+fn unspecified(addr: &SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V4(_) => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+        SocketAddr::V6(_) => SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0),
+    }
+}
+
+fn copy_stream(mut from: TcpStream, mut to: TcpStream) {
+    let _ = io::copy(&mut from, &mut to);
+    let _ = to.shutdown(Shutdown::Write);
+}
+
+/// A userspace TCP relay: accepts connections in the calling thread's
+/// namespace and connects to `target` from inside `ns`, so services bound
+/// to loopback inside the namespace can be reached as well. Dropping the
+/// relay stops listening and closes every relayed connection.
+#[derive(Debug)]
+pub struct TcpRelay {
+    local: SocketAddr,
+    stop: Arc<AtomicBool>,
+    conns: Arc<Mutex<HashMap<usize, (TcpStream, TcpStream)>>>,
+    acceptor: Option<JoinHandle<()>>,
+}
+
+impl TcpRelay {
+    pub fn new(listen: SocketAddr, ns: &NetNS, target: SocketAddr) -> errno::Result<TcpRelay> {
+        let listener = TcpListener::bind(listen)?;
+        let local = listener.local_addr()?;
+        let ns = ns.try_clone()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let conns = Arc::new(Mutex::new(HashMap::new()));
+
+        let (s, c) = (stop.clone(), conns.clone());
+        let acceptor = thread::spawn(move || TcpRelay::accept_loop(listener, ns, target, s, c));
+        Ok(TcpRelay {
+            local,
+            stop,
+            conns,
+            acceptor: Some(acceptor),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local
+    }
+
+    fn accept_loop(
+        listener: TcpListener,
+        ns: NetNS,
+        target: SocketAddr,
+        stop: Arc<AtomicBool>,
+        conns: Arc<Mutex<HashMap<usize, (TcpStream, TcpStream)>>>,
+    ) {
+        let ns = Arc::new(ns);
+        let mut next = 0;
+        for client in listener.incoming() {
+            if stop.load(Ordering::SeqCst) {
+                break;
+            }
+            let client = match client {
+                Ok(v) => v,
+                Err(_) => {
+                    // Out of file descriptors or similar; retrying right
+                    // away would only spin.
+                    thread::sleep(POLL_INTERVAL);
+                    continue;
+                }
+            };
+            let id = next;
+            next += 1;
+            let (ns, stop, conns) = (ns.clone(), stop.clone(), conns.clone());
+            // Connecting may take long, so it must not hold up the next
+            // client or `Drop`.
+            thread::spawn(move || TcpRelay::relay(id, client, &ns, target, &stop, &conns));
+        }
+    }
+
+    fn relay(
+        id: usize,
+        client: TcpStream,
+        ns: &NetNS,
+        target: SocketAddr,
+        stop: &AtomicBool,
+        conns: &Mutex<HashMap<usize, (TcpStream, TcpStream)>>,
+    ) {
+        let upstream = match ns.tcp_connect(target) {
+            Ok(v) => v,
+            Err(_) => return,
+        };
+        let (pair, (c2, u2)) = match (
+            client.try_clone(),
+            upstream.try_clone(),
+            client.try_clone(),
+            upstream.try_clone(),
+        ) {
+            (Ok(a), Ok(b), Ok(c), Ok(d)) => ((a, b), (c, d)),
+            _ => return,
+        };
+        {
+            // `Drop` sets `stop` before it shuts down the registered
+            // connections, so checking under the lock leaves none behind.
+            let mut conns = conns.lock().unwrap_or_else(|e| e.into_inner());
+            if stop.load(Ordering::SeqCst) {
+                return;
+            }
+            conns.insert(id, pair);
+        }
+        let up = thread::spawn(move || copy_stream(c2, u2));
+        copy_stream(upstream, client);
+        let _ = up.join();
+        conns.lock().unwrap_or_else(|e| e.into_inner()).remove(&id);
+    }
+}
+
+impl Drop for TcpRelay {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        let mut wake = self.local;
+        if wake.ip().is_unspecified() {
+            wake.set_ip(match wake {
+                SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            });
+        }
+        let _ = TcpStream::connect_timeout(&wake, POLL_INTERVAL);
+        if let Some(acceptor) = self.acceptor.take() {
+            let _ = acceptor.join();
+        }
+        for (_, (client, upstream)) in self.conns.lock().unwrap_or_else(|e| e.into_inner()).drain()
+        {
+            let _ = client.shutdown(Shutdown::Both);
+            let _ = upstream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+// The reply thread of a session owns its expiry. `last_seen` becomes
+// `None` when it gives up, which it decides with the lock held, so the
+// receive loop can check and refresh a session atomically before using it.
+struct UdpSession {
+    upstream: UdpSocket,
+    last_seen: Arc<Mutex<Option<Instant>>>,
+}
+
+impl UdpSession {
+    // Marks the session as used, unless it has already expired.
+    fn touch(&self) -> bool {
+        let mut seen = self.last_seen.lock().unwrap_or_else(|e| e.into_inner());
+        match *seen {
+            Some(_) => {
+                *seen = Some(Instant::now());
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn expired(&self) -> bool {
+        self.last_seen
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .is_none()
+    }
+}
+
+/// A userspace UDP relay: datagrams received in the calling thread's
+/// namespace are sent to `target` from a socket created inside `ns`, one
+/// per client address, and replies are sent back to the client. Sessions
+/// expire after two minutes without traffic.
+#[derive(Debug)]
+pub struct UdpRelay {
+    local: SocketAddr,
+    stop: Arc<AtomicBool>,
+    receiver: Option<JoinHandle<()>>,
+}
+
+impl UdpRelay {
+    pub fn new(listen: SocketAddr, ns: &NetNS, target: SocketAddr) -> errno::Result<UdpRelay> {
+        let socket = UdpSocket::bind(listen)?;
+        socket.set_read_timeout(Some(POLL_INTERVAL))?;
+        let local = socket.local_addr()?;
+        let ns = ns.try_clone()?;
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let s = stop.clone();
+        let receiver = thread::spawn(move || UdpRelay::recv_loop(socket, ns, target, s));
+        Ok(UdpRelay {
+            local,
+            stop,
+            receiver: Some(receiver),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local
+    }
+
+    fn open_session(
+        socket: &UdpSocket,
+        ns: &NetNS,
+        client: SocketAddr,
+        target: SocketAddr,
+        stop: &Arc<AtomicBool>,
+    ) -> errno::Result<UdpSession> {
+        let upstream = ns.udp_socket(unspecified(&target))?;
+        upstream.connect(target)?;
+        upstream.set_read_timeout(Some(POLL_INTERVAL))?;
+
+        let last_seen = Arc::new(Mutex::new(Some(Instant::now())));
+        let (reply, up, seen, stop) = (
+            socket.try_clone()?,
+            upstream.try_clone()?,
+            last_seen.clone(),
+            stop.clone(),
+        );
+        thread::spawn(move || {
+            let mut buf = vec![0u8; UDP_BUF_SIZE];
+            while !stop.load(Ordering::SeqCst) {
+                match up.recv(&mut buf) {
+                    Ok(n) => {
+                        *seen.lock().unwrap_or_else(|e| e.into_inner()) = Some(Instant::now());
+                        let _ = reply.send_to(&buf[..n], client);
+                    }
+                    Err(ref e)
+                        if e.kind() == io::ErrorKind::WouldBlock
+                            || e.kind() == io::ErrorKind::TimedOut =>
+                    {
+                        let mut seen = seen.lock().unwrap_or_else(|e| e.into_inner());
+                        if !matches!(*seen, Some(t) if t.elapsed() <= UDP_IDLE_TIMEOUT) {
+                            *seen = None;
+                            return;
+                        }
+                    }
+                    Err(_) => {}
+                }
+            }
+            *seen.lock().unwrap_or_else(|e| e.into_inner()) = None;
+        });
+        Ok(UdpSession {
+            upstream,
+            last_seen,
+        })
+    }
+
+    fn recv_loop(socket: UdpSocket, ns: NetNS, target: SocketAddr, stop: Arc<AtomicBool>) {
+        let mut sessions: HashMap<SocketAddr, UdpSession> = HashMap::new();
+        let mut buf = vec![0u8; UDP_BUF_SIZE];
+        let mut pruned = Instant::now();
+        while !stop.load(Ordering::SeqCst) {
+            if pruned.elapsed() > POLL_INTERVAL {
+                sessions.retain(|_, s| !s.expired());
+                pruned = Instant::now();
+            }
+            let (n, client) = match socket.recv_from(&mut buf) {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            if let Entry::Occupied(e) = sessions.entry(client) {
+                if !e.get().touch() {
+                    e.remove();
+                }
+            }
+            let session = match sessions.entry(client) {
+                Entry::Occupied(e) => e.into_mut(),
+                Entry::Vacant(e) => {
+                    match UdpRelay::open_session(&socket, &ns, client, target, &stop) {
+                        Ok(s) => e.insert(s),
+                        Err(_) => continue,
+                    }
+                }
+            };
+            let _ = session.upstream.send(&buf[..n]);
+        }
+    }
+}
+
+impl Drop for UdpRelay {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(receiver) = self.receiver.take() {
+            let _ = receiver.join();
+        }
+    }
+}
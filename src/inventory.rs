@@ -0,0 +1,238 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use super::errno::{self, Errno, Error};
+use super::netns_linux::{fstat, getpid, NetNS};
+
+/// A network namespace found by `NetNS::list_all`, together with what keeps
+/// it alive.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct NetNSInfo {
+    pub dev: u64,
+    pub inode: u64,
+    /// Processes with at least one thread in the namespace.
+    pub pids: Vec<i32>,
+    /// Bind mounts of the namespace, such as `/run/netns/<name>`, and open
+    /// file descriptors referring to it as `/proc/<pid>/fd/<fd>`.
+    pub paths: Vec<String>,
+}
+
+impl NetNSInfo {
+    /// Opens the namespace through one of its paths or processes. Fails
+    /// with `ENOENT` if all of them have gone away since the scan.
+    pub fn open(&self) -> errno::Result<NetNS> {
+        let paths = self.paths.iter().cloned();
+        let procs = self.pids.iter().map(|pid| format!("/proc/{}/ns/net", pid));
+        for path in paths.chain(procs) {
+            if let Ok(ns) = NetNS::get_from_path(&path) {
+                let st = fstat(ns.fd()).ok();
+                if st.is_some_and(|st| st.st_dev == self.dev && st.st_ino == self.inode) {
+                    return Ok(ns);
+                }
+            }
+        }
+        Err(Error::Sys(Errno::from_i32(libc::ENOENT)))
+    }
+}
+
+impl NetNS {
+    /// Finds every network namespace on the host that is used by a thread,
+    /// held open by a file descriptor or bind mounted in the caller's mount
+    /// namespace, sorted by inode. Processes that exit or cannot be
+    /// inspected during the scan are skipped, so run it as root for a
+    /// complete picture.
+    pub fn list_all() -> errno::Result<Vec<NetNSInfo>> {
+        let mut found = BTreeMap::new();
+        for_each_thread(|pid, _, st| add(&mut found, st.dev(), st.ino(), Some(pid), None))?;
+        for pid in proc_pids(Path::new("/proc"))? {
+            let fds = match fs::read_dir(format!("/proc/{}/fd", pid)) {
+                Ok(fds) => fds,
+                Err(_) => continue,
+            };
+            for fd in fds.flatten() {
+                let path = fd.path();
+                let is_netns =
+                    fs::read_link(&path).is_ok_and(|l| l.to_string_lossy().starts_with("net:["));
+                if !is_netns {
+                    continue;
+                }
+                if let Ok(st) = fs::metadata(&path) {
+                    add(
+                        &mut found,
+                        st.dev(),
+                        st.ino(),
+                        None,
+                        Some(path.to_string_lossy().into_owned()),
+                    );
+                }
+            }
+        }
+
+        for line in fs::read_to_string("/proc/self/mountinfo")?.lines() {
+            if let Some(target) = netns_mount(line) {
+                if let Ok(st) = fs::metadata(&target) {
+                    add(&mut found, st.dev(), st.ino(), None, Some(target));
+                }
+            }
+        }
+
+        Ok(found
+            .into_values()
+            .map(|mut info| {
+                info.pids.sort_unstable();
+                info
+            })
+            .collect())
+    }
+
+    /// The processes with at least one thread in this namespace, sorted.
+    pub fn pids(&self) -> errno::Result<Vec<i32>> {
+        let mut pids: Vec<i32> = self.threads()?.into_iter().map(|(pid, _)| pid).collect();
+        pids.dedup();
+        Ok(pids)
+    }
+
+    /// The threads in this namespace as `(pid, tid)` pairs, sorted.
+    pub fn threads(&self) -> errno::Result<Vec<(i32, i32)>> {
+        let own = fstat(self.fd())?;
+        let mut threads = Vec::new();
+        for_each_thread(|pid, tid, st| {
+            if st.dev() == own.st_dev && st.ino() == own.st_ino {
+                threads.push((pid, tid));
+            }
+        })?;
+        threads.sort_unstable();
+        Ok(threads)
+    }
+
+    /// Sends `signal` to every process in this namespace except the
+    /// calling one, and returns the pids it was sent to. Processes that
+    /// exit in the meantime are left out; other failures are reported
+    /// after all processes have been signalled.
+    pub fn kill_all(&self, signal: i32) -> errno::Result<Vec<i32>> {
+        let mut killed = Vec::new();
+        let mut err = None;
+        for pid in self.pids()? {
+            if pid == getpid() {
+                continue;
+            }
+            match Errno::result(unsafe { libc::kill(pid, signal) }) {
+                Ok(_) => killed.push(pid),
+                Err(Error::Sys(e)) if e == Errno::from_i32(libc::ESRCH) => {}
+                Err(e) => err = Some(e),
+            }
+        }
+        match err {
+            Some(e) => Err(e),
+            None => Ok(killed),
+        }
+    }
+}
+
+// Calls `f` with the pid, tid and namespace of every thread whose network
+// namespace can be inspected.
+fn for_each_thread<F: FnMut(i32, i32, fs::Metadata)>(mut f: F) -> errno::Result<()> {
+    for pid in proc_pids(Path::new("/proc"))? {
+        let tasks = proc_pids(Path::new(&format!("/proc/{}/task", pid))).unwrap_or_default();
+        for tid in tasks {
+            if let Ok(st) = fs::metadata(format!("/proc/{}/task/{}/ns/net", pid, tid)) {
+                f(pid, tid, st);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn add(
+    found: &mut BTreeMap<(u64, u64), NetNSInfo>,
+    dev: u64,
+    inode: u64,
+    pid: Option<i32>,
+    path: Option<String>,
+) {
+    let info = found.entry((inode, dev)).or_insert_with(|| NetNSInfo {
+        dev,
+        inode,
+        ..Default::default()
+    });
+    if let Some(pid) = pid {
+        if !info.pids.contains(&pid) {
+            info.pids.push(pid);
+        }
+    }
+    if let Some(path) = path {
+        if !info.paths.contains(&path) {
+            info.paths.push(path);
+        }
+    }
+}
+
+// The numeric entries of a /proc directory.
+fn proc_pids(dir: &Path) -> errno::Result<Vec<i32>> {
+    Ok(fs::read_dir(dir)?
+        .flatten()
+        .filter_map(|e| e.file_name().to_str()?.parse().ok())
+        .collect())
+}
+
+// The mount point of a line from /proc/<pid>/mountinfo if it is a bind
+// mount of a network namespace, see proc(5) for the format.
+fn netns_mount(line: &str) -> Option<String> {
+    let mut fields = line.split(' ');
+    let root = fields.nth(3)?;
+    let target = fields.next()?;
+    fields.position(|f| f == "-")?;
+    if fields.next()? != "nsfs" || !root.starts_with("net:[") {
+        return None;
+    }
+    Some(unescape(target))
+}
+
+// Mount points have spaces, tabs, newlines and backslashes escaped as
+// octal.
+fn unescape(s: &str) -> String {
+    let b = s.as_bytes();
+    let mut out = Vec::with_capacity(b.len());
+    let mut i = 0;
+    while i < b.len() {
+        let octal = b
+            .get(i + 1..i + 4)
+            .is_some_and(|d| d.iter().all(|c| (b'0'..=b'7').contains(c)));
+        if b[i] == b'\\' && octal {
+            let v = b[i + 1..i + 4]
+                .iter()
+                .fold(0u32, |v, c| v * 8 + u32::from(c - b'0'));
+            out.push(v as u8);
+            i += 4;
+        } else {
+            out.push(b[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mountinfo() {
+        assert_eq!(
+            netns_mount(
+                "612 29 0:4 net:[4026532329] /run/netns/a\\040b rw shared:331 - nsfs nsfs rw"
+            ),
+            Some("/run/netns/a b".to_string())
+        );
+        assert_eq!(
+            netns_mount("651 29 0:4 mnt:[4026532330] /run/mnt rw - nsfs nsfs rw"),
+            None
+        );
+        assert_eq!(
+            netns_mount("22 1 0:21 / /proc rw,nosuid - proc proc rw"),
+            None
+        );
+    }
+}
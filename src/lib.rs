@@ -1,10 +1,49 @@
+pub mod builder;
+pub mod command;
+pub mod container;
+pub mod egress;
 pub mod errno;
+pub mod forward;
+pub mod inventory;
+pub mod listener;
+pub mod monitor;
+pub mod mountns;
+pub mod namespace;
+pub mod netlink;
 pub mod netns;
 pub mod netns_linux;
+pub mod nftables;
+pub mod pidns;
+pub mod profile;
+pub mod rtnl;
+pub mod socket;
+pub mod spawn;
+pub mod sysctl;
+pub mod timens;
+pub mod userns;
+pub mod uts;
+pub mod watch;
 
+pub use builder::NetNSBuilder;
+pub use command::CommandExt;
+pub use egress::{Egress, EgressConfig};
 pub use errno::{Errno, Error};
+pub use forward::{DnatForward, TcpRelay, UdpRelay};
+pub use inventory::NetNSInfo;
+pub use listener::MultiListener;
+pub use mountns::{MountNS, Propagation};
+pub use namespace::{Namespace, NsType};
 pub use netns::{apply_in_ns, ExecStatus};
 pub use netns_linux::NetNS;
+pub use nftables::Nftables;
+pub use pidns::PidNS;
+pub use profile::SysctlProfile;
+pub use spawn::{spawn_with_new_namespaces, NsChild};
+pub use sysctl::SysctlValue;
+pub use timens::{Clock, ClockOffset};
+pub use userns::{IdMap, UserNS};
+pub use uts::UtsNS;
+pub use watch::{NetnsEvent, NetnsWatcher};
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,184 @@
+use std::collections::VecDeque;
+use std::io;
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::os::unix::io::AsRawFd;
+
+use super::errno::{self, Errno, Error};
+use super::netns_linux::{close, NetNS};
+
+const MAX_EVENTS: usize = 32;
+
+/// TCP listeners in several network namespaces served from one accept
+/// loop. Each listener is registered under a caller chosen id, which is
+/// returned along with every accepted connection.
+///
+/// ```no_run
+/// use netns::{MultiListener, NetNS};
+///
+/// let tenants = vec![("a", NetNS::get_from_path("/run/netns/a")?)];
+/// let mut server = MultiListener::new()?;
+/// for (id, ns) in tenants.iter() {
+///     server.add(*id, ns, "169.254.169.254:80")?;
+/// }
+/// for conn in server.incoming() {
+///     let (tenant, stream) = conn?;
+///     // serve `stream` for `tenant`
+/// }
+/// # Ok::<(), netns::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct MultiListener<K> {
+    epfd: i32,
+    listeners: Vec<Option<(K, TcpListener)>>,
+    pending: VecDeque<(K, TcpStream)>,
+}
+
+impl<K> Drop for MultiListener<K> {
+    fn drop(&mut self) {
+        let _ = close(self.epfd);
+    }
+}
+
+impl<K: Clone + PartialEq> MultiListener<K> {
+    pub fn new() -> errno::Result<MultiListener<K>> {
+        let epfd = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+        Ok(MultiListener {
+            epfd: Errno::result(epfd)?,
+            listeners: Vec::new(),
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// Listens on `addr` in every given namespace.
+    pub fn bind<'a, I, A>(namespaces: I, addr: A) -> errno::Result<MultiListener<K>>
+    where
+        I: IntoIterator<Item = (K, &'a NetNS)>,
+        A: ToSocketAddrs + Copy,
+    {
+        let mut server = MultiListener::new()?;
+        for (id, ns) in namespaces {
+            server.add(id, ns, addr)?;
+        }
+        Ok(server)
+    }
+
+    /// Starts listening on `addr` inside `ns`, reporting connections under
+    /// `id`.
+    pub fn add<A: ToSocketAddrs>(&mut self, id: K, ns: &NetNS, addr: A) -> errno::Result<()> {
+        let listener = ns.tcp_listener(addr)?;
+        listener.set_nonblocking(true)?;
+
+        let slot = match self.listeners.iter().position(|l| l.is_none()) {
+            Some(i) => i,
+            None => {
+                self.listeners.push(None);
+                self.listeners.len() - 1
+            }
+        };
+        let mut ev = libc::epoll_event {
+            events: libc::EPOLLIN as u32,
+            u64: slot as u64,
+        };
+        let res = unsafe {
+            libc::epoll_ctl(
+                self.epfd,
+                libc::EPOLL_CTL_ADD,
+                listener.as_raw_fd(),
+                &mut ev,
+            )
+        };
+        Errno::result(res)?;
+        self.listeners[slot] = Some((id, listener));
+        Ok(())
+    }
+
+    /// Stops listening for `id`. Returns false if no listener has that id.
+    pub fn remove(&mut self, id: &K) -> bool {
+        let slot = self
+            .listeners
+            .iter()
+            .position(|l| matches!(l, Some((k, _)) if k == id));
+        match slot {
+            Some(i) => {
+                if let Some((_, listener)) = self.listeners[i].take() {
+                    unsafe {
+                        libc::epoll_ctl(
+                            self.epfd,
+                            libc::EPOLL_CTL_DEL,
+                            listener.as_raw_fd(),
+                            std::ptr::null_mut(),
+                        );
+                    }
+                }
+                self.pending.retain(|(k, _)| k != id);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.listeners.iter().filter(|l| l.is_some()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Waits for a connection on any listener.
+    pub fn accept(&mut self) -> errno::Result<(K, TcpStream)> {
+        loop {
+            if let Some(conn) = self.pending.pop_front() {
+                return Ok(conn);
+            }
+            if self.is_empty() {
+                return Err(Error::Sys(Errno::from_i32(libc::EINVAL)));
+            }
+
+            let mut events = [libc::epoll_event { events: 0, u64: 0 }; MAX_EVENTS];
+            let n =
+                unsafe { libc::epoll_wait(self.epfd, events.as_mut_ptr(), MAX_EVENTS as i32, -1) };
+            if n < 0 {
+                let e = Errno::last();
+                if e == Errno::from_i32(libc::EINTR) {
+                    continue;
+                }
+                return Err(Error::Sys(e));
+            }
+
+            for ev in events[..n as usize].iter() {
+                let slot = ev.u64 as usize;
+                let (id, listener) = match self.listeners.get(slot) {
+                    Some(Some(l)) => l,
+                    _ => continue,
+                };
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        stream.set_nonblocking(false)?;
+                        self.pending.push_back((id.clone(), stream));
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        }
+    }
+
+    pub fn incoming(&mut self) -> Incoming<'_, K> {
+        Incoming { server: self }
+    }
+}
+
+/// Iterator over connections accepted by a `MultiListener`. Never yields
+/// `None`.
+pub struct Incoming<'a, K> {
+    server: &'a mut MultiListener<K>,
+}
+
+impl<'a, K: Clone + PartialEq> Iterator for Incoming<'a, K> {
+    type Item = errno::Result<(K, TcpStream)>;
+
+    fn next(&mut self) -> Option<errno::Result<(K, TcpStream)>> {
+        Some(self.server.accept())
+    }
+}
@@ -0,0 +1,377 @@
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use super::errno::{self, Errno, Error};
+use super::netlink::{self, Attrs, Reply, Socket};
+use super::netns_linux::NetNS;
+use super::rtnl::{
+    parse_nsid, IFA_ADDRESS, IFA_LOCAL, IFF_UP, IFLA_IFNAME, RTA_DST, RTA_GATEWAY, RTA_OIF,
+    RTM_DELADDR, RTM_DELLINK, RTM_DELNSID, RTM_DELROUTE, RTM_NEWADDR, RTM_NEWLINK, RTM_NEWNSID,
+    RTM_NEWROUTE,
+};
+
+const RTM_NEWNEIGH: u16 = 28;
+const RTM_DELNEIGH: u16 = 29;
+
+const IFF_LOWER_UP: u32 = 0x10000;
+const RTA_TABLE: u16 = 15;
+const NDA_DST: u16 = 1;
+const NDA_LLADDR: u16 = 2;
+
+/// The rtnetlink multicast groups a `Monitor` can subscribe to.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Group {
+    Link,
+    Neigh,
+    Ipv4Addr,
+    Ipv4Route,
+    Ipv6Addr,
+    Ipv6Route,
+    NsId,
+}
+
+impl Group {
+    /// The `RTNLGRP_*` number of this group.
+    pub fn number(self) -> u32 {
+        match self {
+            Group::Link => 1,
+            Group::Neigh => 3,
+            Group::Ipv4Addr => 5,
+            Group::Ipv4Route => 7,
+            Group::Ipv6Addr => 9,
+            Group::Ipv6Route => 11,
+            Group::NsId => 28,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Link {
+    pub index: u32,
+    pub name: String,
+    /// The `IFF_*` flags of the link.
+    pub flags: u32,
+}
+
+impl Link {
+    /// Whether the link is administratively up.
+    pub fn is_up(&self) -> bool {
+        self.flags & IFF_UP != 0
+    }
+
+    /// Whether the link has a carrier.
+    pub fn is_lower_up(&self) -> bool {
+        self.flags & IFF_LOWER_UP != 0
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Addr {
+    pub index: u32,
+    pub addr: IpAddr,
+    pub prefix: u8,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Route {
+    pub family: u8,
+    /// The destination, `None` for a default route.
+    pub dst: Option<IpAddr>,
+    pub prefix: u8,
+    pub gateway: Option<IpAddr>,
+    pub oif: Option<u32>,
+    pub table: u32,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Neigh {
+    pub index: u32,
+    pub addr: IpAddr,
+    pub lladdr: Option<Vec<u8>>,
+    /// The `NUD_*` state of the entry.
+    pub state: u16,
+}
+
+/// A change reported by rtnetlink. Links that change state, e.g. go up or
+/// down, are reported as `NewLink` with the new flags.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Event {
+    NewLink(Link),
+    DelLink(Link),
+    NewAddr(Addr),
+    DelAddr(Addr),
+    NewRoute(Route),
+    DelRoute(Route),
+    NewNeigh(Neigh),
+    DelNeigh(Neigh),
+    /// An ID was assigned to a peer namespace. Messages for peers without
+    /// an ID are not reported.
+    NewNsId(i32),
+    DelNsId(i32),
+}
+
+/// A subscription to rtnetlink events in one network namespace, see
+/// `NetNS::monitor`. Iterating blocks until the next event; an `ENOBUFS`
+/// error means events were lost because they were not read fast enough,
+/// after which iteration can continue.
+///
+/// There is no async stream; to use it with an async runtime, switch the
+/// socket to non-blocking mode, register `as_raw_fd()` with the runtime and
+/// call `try_recv` whenever it becomes readable.
+#[derive(Debug)]
+pub struct Monitor {
+    sock: Socket,
+    queue: VecDeque<Event>,
+}
+
+impl NetNS {
+    /// Subscribes to `groups` in this namespace.
+    pub fn monitor(&self, groups: &[Group]) -> errno::Result<Monitor> {
+        let mut sock = Socket::new_in(self, netlink::NETLINK_ROUTE)?;
+        for group in groups {
+            sock.add_membership(group.number())?;
+        }
+        Ok(Monitor {
+            sock,
+            queue: VecDeque::new(),
+        })
+    }
+}
+
+impl Monitor {
+    pub fn set_nonblocking(&self, nonblocking: bool) -> errno::Result<()> {
+        let flags = Errno::result(unsafe { libc::fcntl(self.sock.fd(), libc::F_GETFL) })?;
+        let flags = if nonblocking {
+            flags | libc::O_NONBLOCK
+        } else {
+            flags & !libc::O_NONBLOCK
+        };
+        Errno::result(unsafe { libc::fcntl(self.sock.fd(), libc::F_SETFL, flags) })?;
+        Ok(())
+    }
+
+    /// Returns the next event without waiting, or `None` if none is
+    /// pending.
+    pub fn try_recv(&mut self) -> errno::Result<Option<Event>> {
+        self.next_event(libc::MSG_DONTWAIT)
+    }
+
+    fn next_event(&mut self, flags: i32) -> errno::Result<Option<Event>> {
+        while self.queue.is_empty() {
+            let replies = match self.sock.recv(flags) {
+                Ok(v) => v,
+                Err(Error::Sys(e)) if e == Errno::from_i32(libc::EAGAIN) => return Ok(None),
+                Err(e) => return Err(e),
+            };
+            self.queue.extend(replies.iter().filter_map(parse_event));
+        }
+        Ok(self.queue.pop_front())
+    }
+}
+
+impl Iterator for Monitor {
+    type Item = errno::Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_event(0).transpose()
+    }
+}
+
+impl AsRawFd for Monitor {
+    fn as_raw_fd(&self) -> RawFd {
+        self.sock.fd()
+    }
+}
+
+fn parse_ip(family: u8, data: &[u8]) -> Option<IpAddr> {
+    match i32::from(family) {
+        libc::AF_INET if data.len() >= 4 => {
+            Some(IpAddr::from([data[0], data[1], data[2], data[3]]))
+        }
+        libc::AF_INET6 if data.len() >= 16 => {
+            let mut b = [0u8; 16];
+            b.copy_from_slice(&data[..16]);
+            Some(IpAddr::from(b))
+        }
+        _ => None,
+    }
+}
+
+fn parse_link(p: &[u8]) -> Option<Link> {
+    let mut link = Link {
+        index: netlink::parse_u32(p.get(4..8)?)?,
+        name: String::new(),
+        flags: netlink::parse_u32(p.get(8..12)?)?,
+    };
+    for (ty, data) in Attrs::new(p.get(16..)?) {
+        if ty == IFLA_IFNAME {
+            link.name = netlink::parse_str(data);
+        }
+    }
+    Some(link)
+}
+
+fn parse_addr(p: &[u8]) -> Option<Addr> {
+    let family = *p.first()?;
+    let (mut local, mut address) = (None, None);
+    for (ty, data) in Attrs::new(p.get(8..)?) {
+        match ty {
+            IFA_LOCAL => local = parse_ip(family, data),
+            IFA_ADDRESS => address = parse_ip(family, data),
+            _ => {}
+        }
+    }
+    Some(Addr {
+        index: netlink::parse_u32(p.get(4..8)?)?,
+        // IFA_ADDRESS is the peer on point-to-point links.
+        addr: local.or(address)?,
+        prefix: p[1],
+    })
+}
+
+fn parse_route(p: &[u8]) -> Option<Route> {
+    let family = *p.first()?;
+    let mut route = Route {
+        family,
+        dst: None,
+        prefix: *p.get(1)?,
+        gateway: None,
+        oif: None,
+        table: u32::from(*p.get(4)?),
+    };
+    for (ty, data) in Attrs::new(p.get(12..)?) {
+        match ty {
+            RTA_DST => route.dst = parse_ip(family, data),
+            RTA_GATEWAY => route.gateway = parse_ip(family, data),
+            RTA_OIF => route.oif = netlink::parse_u32(data),
+            RTA_TABLE => route.table = netlink::parse_u32(data)?,
+            _ => {}
+        }
+    }
+    Some(route)
+}
+
+fn parse_neigh(p: &[u8]) -> Option<Neigh> {
+    let family = *p.first()?;
+    let (mut addr, mut lladdr) = (None, None);
+    for (ty, data) in Attrs::new(p.get(12..)?) {
+        match ty {
+            NDA_DST => addr = parse_ip(family, data),
+            NDA_LLADDR => lladdr = Some(data.to_vec()),
+            _ => {}
+        }
+    }
+    Some(Neigh {
+        index: netlink::parse_u32(p.get(4..8)?)?,
+        addr: addr?,
+        lladdr,
+        state: netlink::parse_u16(p.get(8..10)?)?,
+    })
+}
+
+// Messages of other types and ones too short to parse are skipped, as are
+// NSID messages without an assigned ID.
+fn parse_event(reply: &Reply) -> Option<Event> {
+    let p = &reply.payload;
+    Some(match reply.ty {
+        RTM_NEWLINK => Event::NewLink(parse_link(p)?),
+        RTM_DELLINK => Event::DelLink(parse_link(p)?),
+        RTM_NEWADDR => Event::NewAddr(parse_addr(p)?),
+        RTM_DELADDR => Event::DelAddr(parse_addr(p)?),
+        RTM_NEWROUTE => Event::NewRoute(parse_route(p)?),
+        RTM_DELROUTE => Event::DelRoute(parse_route(p)?),
+        RTM_NEWNEIGH => Event::NewNeigh(parse_neigh(p)?),
+        RTM_DELNEIGH => Event::DelNeigh(parse_neigh(p)?),
+        RTM_NEWNSID => Event::NewNsId(parse_nsid(p)?),
+        RTM_DELNSID => Event::DelNsId(parse_nsid(p)?),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::rtnl::{ifinfomsg, NETNSA_NSID};
+    use super::*;
+
+    fn attr(ty: u16, data: &[u8]) -> Vec<u8> {
+        let mut b = Vec::new();
+        b.extend_from_slice(&((4 + data.len()) as u16).to_ne_bytes());
+        b.extend_from_slice(&ty.to_ne_bytes());
+        b.extend_from_slice(data);
+        b.resize(netlink::align(b.len()), 0);
+        b
+    }
+
+    #[test]
+    fn events() {
+        let mut link = ifinfomsg(0, 3, IFF_UP | IFF_LOWER_UP, 0).to_vec();
+        link.extend(attr(IFLA_IFNAME, b"veth0\0"));
+        let reply = Reply {
+            ty: RTM_NEWLINK,
+            flags: 0,
+            seq: 0,
+            payload: link,
+        };
+        match parse_event(&reply) {
+            Some(Event::NewLink(l)) => {
+                assert_eq!((l.index, l.name.as_str()), (3, "veth0"));
+                assert!(l.is_up() && l.is_lower_up());
+            }
+            e => panic!("{:?}", e),
+        }
+
+        let mut addr = vec![libc::AF_INET as u8, 24, 0, 0];
+        addr.extend_from_slice(&7u32.to_ne_bytes());
+        addr.extend(attr(IFA_ADDRESS, &[10, 0, 0, 2]));
+        addr.extend(attr(IFA_LOCAL, &[10, 0, 0, 1]));
+        let reply = Reply {
+            ty: RTM_DELADDR,
+            flags: 0,
+            seq: 0,
+            payload: addr,
+        };
+        assert_eq!(
+            parse_event(&reply),
+            Some(Event::DelAddr(Addr {
+                index: 7,
+                addr: IpAddr::from([10, 0, 0, 1]),
+                prefix: 24,
+            }))
+        );
+
+        let mut route = vec![libc::AF_INET as u8, 0, 0, 0, 254, 3, 0, 1, 0, 0, 0, 0];
+        route.extend(attr(RTA_GATEWAY, &[10, 0, 0, 254]));
+        route.extend(attr(RTA_OIF, &7u32.to_ne_bytes()));
+        let reply = Reply {
+            ty: RTM_NEWROUTE,
+            flags: 0,
+            seq: 0,
+            payload: route,
+        };
+        assert_eq!(
+            parse_event(&reply),
+            Some(Event::NewRoute(Route {
+                family: libc::AF_INET as u8,
+                dst: None,
+                prefix: 0,
+                gateway: Some(IpAddr::from([10, 0, 0, 254])),
+                oif: Some(7),
+                table: 254,
+            }))
+        );
+
+        let nsid = |id: i32| {
+            let mut p = vec![libc::AF_UNSPEC as u8, 0, 0, 0];
+            p.extend(attr(NETNSA_NSID, &id.to_ne_bytes()));
+            Reply {
+                ty: RTM_NEWNSID,
+                flags: 0,
+                seq: 0,
+                payload: p,
+            }
+        };
+        assert_eq!(parse_event(&nsid(4)), Some(Event::NewNsId(4)));
+        assert_eq!(parse_event(&nsid(-1)), None);
+    }
+}
@@ -0,0 +1,207 @@
+use std::ffi::{CStr, CString};
+
+use super::errno::{self, Errno, Error};
+use super::namespace::{Namespace, NsType};
+use super::netns_linux::{getpid, gettid, mount, umount2, unshare, CLONE_NEWNS};
+
+/// Mount propagation, see mount_namespaces(7).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Propagation {
+    Private,
+    Slave,
+    Shared,
+    Unbindable,
+}
+
+impl Propagation {
+    fn flag(self) -> libc::c_ulong {
+        match self {
+            Propagation::Private => libc::MS_PRIVATE,
+            Propagation::Slave => libc::MS_SLAVE,
+            Propagation::Shared => libc::MS_SHARED,
+            Propagation::Unbindable => libc::MS_UNBINDABLE,
+        }
+    }
+}
+
+fn cstring(s: &str) -> errno::Result<CString> {
+    CString::new(s).map_err(|_| Error::Sys(Errno::from_i32(libc::EINVAL)))
+}
+
+pub(crate) fn cstr(s: &'static [u8]) -> &'static CStr {
+    CStr::from_bytes_with_nul(s).unwrap()
+}
+
+/// A mount namespace. Like network namespaces, mount namespaces are per
+/// thread here: `new` and `set` only affect the calling thread, and `run`
+/// uses a helper thread.
+#[derive(Debug)]
+pub struct MountNS {
+    ns: Namespace,
+}
+
+impl MountNS {
+    /// Moves the calling thread into a new mount namespace. Like
+    /// unshare(1), all mounts are made private first so nothing done in
+    /// the new namespace propagates back.
+    pub fn new() -> errno::Result<MountNS> {
+        MountNS::new_with(Propagation::Private)
+    }
+
+    /// Like `new` with the given propagation for the copied mounts.
+    /// `Propagation::Slave` still receives new mounts from the parent
+    /// namespace, which is what `ip netns exec` uses.
+    pub fn new_with(propagation: Propagation) -> errno::Result<MountNS> {
+        unshare(CLONE_NEWNS)?;
+        set_propagation("/", propagation, true)?;
+        MountNS::get()
+    }
+
+    /// Like `new_with`, but the calling thread keeps its mount namespace.
+    pub fn new_detached(propagation: Propagation) -> errno::Result<MountNS> {
+        let ns = Namespace::new_detached(NsType::Mnt)?;
+        ns.run(|| set_propagation("/", propagation, true))?;
+        Ok(MountNS { ns })
+    }
+
+    pub fn get() -> errno::Result<MountNS> {
+        Ok(MountNS {
+            ns: Namespace::get_from_thread(getpid(), gettid(), NsType::Mnt)?,
+        })
+    }
+
+    pub fn get_from_process(pid: i32) -> errno::Result<MountNS> {
+        Ok(MountNS {
+            ns: Namespace::get_from_process(pid, NsType::Mnt)?,
+        })
+    }
+
+    pub fn set(ns: &MountNS) -> errno::Result<()> {
+        Namespace::set(&ns.ns)
+    }
+
+    pub fn namespace(&self) -> &Namespace {
+        &self.ns
+    }
+
+    /// Runs `f` on a short-lived thread that has joined this namespace.
+    pub fn run<F, T>(&self, f: F) -> errno::Result<T>
+    where
+        F: FnOnce() -> errno::Result<T> + Send,
+        T: Send,
+    {
+        self.ns.run(f)
+    }
+}
+
+// The functions below act on the calling thread's mount namespace.
+
+/// Changes the propagation type of the mount at `target`, and of all
+/// mounts below it if `recursive` is set.
+pub fn set_propagation(
+    target: &str,
+    propagation: Propagation,
+    recursive: bool,
+) -> errno::Result<()> {
+    let mut flags = propagation.flag();
+    if recursive {
+        flags |= libc::MS_REC;
+    }
+    mount(None, &cstring(target)?, None, flags, None)
+}
+
+/// Bind mounts `source` with all mounts below it onto `target`, like
+/// `mount --rbind`, optionally read-only.
+pub fn bind_mount(source: &str, target: &str, readonly: bool) -> errno::Result<()> {
+    let target = cstring(target)?;
+    mount(
+        Some(&cstring(source)?),
+        &target,
+        None,
+        libc::MS_BIND | libc::MS_REC,
+        None,
+    )?;
+    if readonly {
+        // Bind mounts only become read-only through a remount.
+        mount(
+            None,
+            &target,
+            None,
+            libc::MS_BIND | libc::MS_REMOUNT | libc::MS_RDONLY,
+            None,
+        )?;
+    }
+    Ok(())
+}
+
+/// Mounts a tmpfs on `target`. `options` are passed as mount data, e.g.
+/// `"size=16m,mode=755"`.
+pub fn mount_tmpfs(target: &str, options: Option<&str>) -> errno::Result<()> {
+    let options = match options {
+        Some(o) => Some(cstring(o)?),
+        None => None,
+    };
+    mount(
+        Some(cstr(b"tmpfs\0")),
+        &cstring(target)?,
+        Some(cstr(b"tmpfs\0")),
+        libc::MS_NOSUID | libc::MS_NODEV,
+        options.as_deref(),
+    )
+}
+
+pub fn unmount(target: &str, detach: bool) -> errno::Result<()> {
+    let flags = if detach { libc::MNT_DETACH } else { 0 };
+    umount2(&cstring(target)?, flags)
+}
+
+/// Replaces `/proc` with a fresh instance, so it shows the processes of
+/// the calling thread's PID namespace.
+pub fn remount_proc() -> errno::Result<()> {
+    let proc_ = cstr(b"/proc\0");
+    let _ = umount2(proc_, libc::MNT_DETACH);
+    mount(
+        Some(cstr(b"proc\0")),
+        proc_,
+        Some(cstr(b"proc\0")),
+        libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+        None,
+    )
+}
+
+/// Replaces `/sys/fs/cgroup` with a fresh cgroup2 instance rooted at the
+/// calling thread's cgroup namespace, hiding the rest of the host's cgroup
+/// tree.
+pub fn remount_cgroup() -> errno::Result<()> {
+    let target = cstr(b"/sys/fs/cgroup\0");
+    let _ = umount2(target, libc::MNT_DETACH);
+    mount(
+        Some(cstr(b"cgroup2\0")),
+        target,
+        Some(cstr(b"cgroup2\0")),
+        libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+        None,
+    )
+}
+
+/// Replaces `/sys` with a fresh instance, so `/sys/class/net` lists the
+/// interfaces of the calling thread's network namespace.
+pub fn remount_sys() -> errno::Result<()> {
+    remount_sysfs(cstr(b"sysfs\0"))
+}
+
+// Does not allocate, so it can also be used between fork and exec. If the
+// old instance cannot be unmounted it is shadowed instead, which has to be
+// read-only if it was.
+pub(crate) fn remount_sysfs(source: &CStr) -> errno::Result<()> {
+    let sys = cstr(b"/sys\0");
+    let mut flags = 0;
+    if umount2(sys, libc::MNT_DETACH).is_err() {
+        let mut st: libc::statvfs = unsafe { std::mem::zeroed() };
+        if unsafe { libc::statvfs(sys.as_ptr(), &mut st) } == 0 && st.f_flag & libc::ST_RDONLY != 0
+        {
+            flags = libc::MS_RDONLY;
+        }
+    }
+    mount(Some(source), sys, Some(cstr(b"sysfs\0")), flags, None)
+}
@@ -0,0 +1,309 @@
+use super::errno::{self, Errno};
+use super::netns_linux::{
+    close, fstat, getpid, gettid, on_helper_thread, open, setns, unshare, NetNS, CLONE_NEWCGROUP,
+    CLONE_NEWIPC, CLONE_NEWNET, CLONE_NEWNS, CLONE_NEWPID, CLONE_NEWTIME, CLONE_NEWUSER,
+    CLONE_NEWUTS, O_RDONLY,
+};
+
+// nsfs ioctls, see ioctl_ns(2).
+const NS_GET_USERNS: u64 = 0xb701;
+const NS_GET_PARENT: u64 = 0xb702;
+const NS_GET_NSTYPE: u64 = 0xb703;
+const NS_GET_OWNER_UID: u64 = 0xb704;
+
+/// The kinds of Linux namespace a `Namespace` handle can refer to.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum NsType {
+    Net,
+    Uts,
+    Ipc,
+    User,
+    Pid,
+    Mnt,
+    Cgroup,
+    Time,
+}
+
+impl NsType {
+    pub const ALL: [NsType; 8] = [
+        NsType::User,
+        NsType::Mnt,
+        NsType::Uts,
+        NsType::Ipc,
+        NsType::Pid,
+        NsType::Net,
+        NsType::Cgroup,
+        NsType::Time,
+    ];
+
+    /// The types whose flags are set in `flags`.
+    pub fn from_flags(flags: i32) -> Vec<NsType> {
+        NsType::ALL
+            .iter()
+            .cloned()
+            .filter(|ty| flags & ty.flag() != 0)
+            .collect()
+    }
+
+    /// The type whose `CLONE_NEW*` flag is `flag`.
+    pub fn from_flag(flag: i32) -> Option<NsType> {
+        NsType::ALL.iter().cloned().find(|ty| ty.flag() == flag)
+    }
+
+    /// The `CLONE_NEW*` flag for this type, as taken by unshare(2) and
+    /// setns(2).
+    pub fn flag(self) -> i32 {
+        match self {
+            NsType::Net => CLONE_NEWNET,
+            NsType::Uts => CLONE_NEWUTS,
+            NsType::Ipc => CLONE_NEWIPC,
+            NsType::User => CLONE_NEWUSER,
+            NsType::Pid => CLONE_NEWPID,
+            NsType::Mnt => CLONE_NEWNS,
+            NsType::Cgroup => CLONE_NEWCGROUP,
+            NsType::Time => CLONE_NEWTIME,
+        }
+    }
+
+    /// The file name under `/proc/<pid>/ns/`.
+    pub fn proc_name(self) -> &'static str {
+        match self {
+            NsType::Net => "net",
+            NsType::Uts => "uts",
+            NsType::Ipc => "ipc",
+            NsType::User => "user",
+            NsType::Pid => "pid",
+            NsType::Mnt => "mnt",
+            NsType::Cgroup => "cgroup",
+            NsType::Time => "time",
+        }
+    }
+}
+
+/// A handle to a namespace of any type, opened from `/proc/<pid>/ns/*` or
+/// a bind mount of it. `NetNS` wraps one of type `NsType::Net`. Compare
+/// handles with `same_as`.
+#[derive(Debug)]
+pub struct Namespace {
+    fd: i32,
+    ty: NsType,
+    path: String,
+}
+
+impl Drop for Namespace {
+    fn drop(&mut self) {
+        let _ = close(self.fd);
+    }
+}
+
+impl Namespace {
+    /// Moves the calling thread into a new namespace of type `ty`, like
+    /// `NetNS::new`. A new time namespace only applies to children started
+    /// afterwards, and the returned handle refers to it. PID namespaces
+    /// cannot be opened before they have an init process and fail with
+    /// `EINVAL`; use `PidNS` for them. User namespaces can only be created
+    /// by single-threaded processes, see `UserNS`.
+    pub fn new(ty: NsType) -> errno::Result<Namespace> {
+        if ty == NsType::Pid {
+            return Err(errno::Error::Sys(Errno::from_i32(libc::EINVAL)));
+        }
+        unshare(ty.flag())?;
+        match ty {
+            NsType::Time => Namespace::get_from_path(
+                &format!("/proc/{}/task/{}/ns/time_for_children", getpid(), gettid()),
+                ty,
+            ),
+            _ => Namespace::get(ty),
+        }
+    }
+
+    /// Creates a namespace of type `ty` without moving the calling thread
+    /// into it.
+    pub fn new_detached(ty: NsType) -> errno::Result<Namespace> {
+        on_helper_thread(|| Namespace::new(ty)).map(Namespace::into_fd_path)
+    }
+
+    /// The calling thread's namespace of type `ty`.
+    pub fn get(ty: NsType) -> errno::Result<Namespace> {
+        Namespace::get_from_thread(getpid(), gettid(), ty)
+    }
+
+    /// Moves the calling thread into `ns`. For a mount namespace the
+    /// thread first stops sharing its root and working directory with the
+    /// other threads, which the kernel requires.
+    pub fn set(ns: &Namespace) -> errno::Result<()> {
+        if ns.ty == NsType::Mnt {
+            unshare(libc::CLONE_FS)?;
+        }
+        setns(ns.fd, ns.ty.flag())?;
+        Ok(())
+    }
+
+    pub fn get_from_thread(pid: i32, tid: i32, ty: NsType) -> errno::Result<Namespace> {
+        Namespace::get_from_path(
+            &format!("/proc/{}/task/{}/ns/{}", pid, tid, ty.proc_name()),
+            ty,
+        )
+    }
+
+    pub fn get_from_process(pid: i32, ty: NsType) -> errno::Result<Namespace> {
+        Namespace::get_from_path(&format!("/proc/{}/ns/{}", pid, ty.proc_name()), ty)
+    }
+
+    pub fn get_from_path(path: &str, ty: NsType) -> errno::Result<Namespace> {
+        let fd = open(path.to_string(), O_RDONLY, 0)?;
+        Ok(Namespace {
+            fd,
+            ty,
+            path: path.to_string(),
+        })
+    }
+
+    /// Opens the namespace at `path`, asking the kernel for its type.
+    pub fn open(path: &str) -> errno::Result<Namespace> {
+        let fd = open(path.to_string(), O_RDONLY, 0)?;
+        let ty = match nstype(fd) {
+            Ok(ty) => ty,
+            Err(e) => {
+                let _ = close(fd);
+                return Err(e);
+            }
+        };
+        Ok(Namespace {
+            fd,
+            ty,
+            path: path.to_string(),
+        })
+    }
+
+    // Takes ownership of a namespace fd that has no path of its own.
+    pub(crate) fn from_fd(fd: i32, ty: NsType) -> Namespace {
+        Namespace {
+            fd,
+            ty,
+            path: format!("/proc/{}/fd/{}", getpid(), fd),
+        }
+    }
+
+    // The `task/<tid>` path of a handle made on a helper thread is gone
+    // once the thread exits, so refer to the descriptor instead.
+    pub(crate) fn into_fd_path(mut self) -> Namespace {
+        self.path = format!("/proc/{}/fd/{}", getpid(), self.fd);
+        self
+    }
+
+    pub fn try_clone(&self) -> errno::Result<Namespace> {
+        let fd = unsafe { libc::fcntl(self.fd, libc::F_DUPFD_CLOEXEC, 0) };
+        Ok(Namespace {
+            fd: Errno::result(fd)?,
+            ty: self.ty,
+            path: self.path.clone(),
+        })
+    }
+
+    pub fn fd(&self) -> i32 {
+        self.fd
+    }
+
+    pub fn ns_type(&self) -> NsType {
+        self.ty
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The nsfs inode number identifying this namespace.
+    pub fn inode(&self) -> errno::Result<u64> {
+        Ok(fstat(self.fd)?.st_ino)
+    }
+
+    pub fn same_as(&self, other: &Namespace) -> errno::Result<bool> {
+        let (a, b) = (fstat(self.fd)?, fstat(other.fd)?);
+        Ok(self.ty == other.ty && a.st_dev == b.st_dev && a.st_ino == b.st_ino)
+    }
+
+    /// The user namespace that owns this namespace, i.e. the one whose
+    /// capabilities govern it. Fails with `EPERM` if that namespace is
+    /// outside the caller's user namespace.
+    pub fn owner(&self) -> errno::Result<Namespace> {
+        ns_ioctl(self.fd, NS_GET_USERNS, NsType::User)
+    }
+
+    /// The parent of a PID or user namespace. Fails with `EPERM` for the
+    /// caller's own root, and with `EINVAL` for other types.
+    pub fn parent(&self) -> errno::Result<Namespace> {
+        ns_ioctl(self.fd, NS_GET_PARENT, self.ty)
+    }
+
+    /// The uid of the process that created this user namespace, as seen
+    /// from the caller's user namespace. Fails with `EINVAL` for other
+    /// types; use `owner()?.owner_uid()` for those.
+    pub fn owner_uid(&self) -> errno::Result<u32> {
+        let mut uid: libc::uid_t = 0;
+        let res = unsafe { libc::ioctl(self.fd, NS_GET_OWNER_UID as _, &mut uid) };
+        Errno::result(res)?;
+        Ok(uid)
+    }
+
+    /// Runs `f` on a short-lived thread that has joined this namespace,
+    /// like `NetNS::run`. User and time namespaces cannot be joined this
+    /// way, as the kernel only lets single-threaded processes change them;
+    /// use `CommandExt::namespaces` instead. Joining a PID namespace only
+    /// affects processes `f` starts.
+    pub fn run<F, T>(&self, f: F) -> errno::Result<T>
+    where
+        F: FnOnce() -> errno::Result<T> + Send,
+        T: Send,
+    {
+        on_helper_thread(move || {
+            Namespace::set(self)?;
+            f()
+        })
+    }
+}
+
+impl NetNS {
+    /// Returns a generic handle to this network namespace.
+    pub fn to_namespace(&self) -> errno::Result<Namespace> {
+        self.namespace().try_clone()
+    }
+
+    /// The user namespace that owns this network namespace.
+    pub fn owner(&self) -> errno::Result<Namespace> {
+        self.namespace().owner()
+    }
+}
+
+fn nstype(fd: i32) -> errno::Result<NsType> {
+    let flag = unsafe { libc::ioctl(fd, NS_GET_NSTYPE as _) };
+    NsType::from_flag(Errno::result(flag)?).ok_or(errno::Error::Sys(Errno::from_i32(libc::EINVAL)))
+}
+
+// Runs an nsfs ioctl that returns a new namespace file descriptor, which
+// the kernel opens with O_CLOEXEC.
+fn ns_ioctl(fd: i32, request: u64, ty: NsType) -> errno::Result<Namespace> {
+    let fd = Errno::result(unsafe { libc::ioctl(fd, request as _) })?;
+    Ok(Namespace::from_fd(fd, ty))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detached_path_reopens() {
+        // Creating namespaces needs root.
+        if unsafe { libc::geteuid() } != 0 {
+            return;
+        }
+        for ty in [NsType::Ipc, NsType::Uts, NsType::Mnt] {
+            let ns = Namespace::new_detached(ty).unwrap();
+            let reopened = Namespace::open(ns.path()).unwrap();
+            assert!(reopened.same_as(&ns).unwrap());
+        }
+        let netns = NetNS::builder().build_detached().unwrap();
+        let reopened = NetNS::get_from_path(netns.path()).unwrap();
+        assert!(reopened.same_as(&netns).unwrap());
+    }
+}
@@ -0,0 +1,515 @@
+use std::mem;
+use std::net::IpAddr;
+
+use super::errno::{self, Errno, Error};
+use super::netns_linux::{close, NetNS};
+
+pub const NETLINK_ROUTE: i32 = 0;
+pub const NETLINK_NETFILTER: i32 = 12;
+
+pub const NLM_F_REQUEST: u16 = 0x01;
+pub const NLM_F_MULTI: u16 = 0x02;
+pub const NLM_F_ACK: u16 = 0x04;
+pub const NLM_F_ECHO: u16 = 0x08;
+pub const NLM_F_ROOT: u16 = 0x100;
+pub const NLM_F_MATCH: u16 = 0x200;
+pub const NLM_F_DUMP: u16 = NLM_F_ROOT | NLM_F_MATCH;
+pub const NLM_F_REPLACE: u16 = 0x100;
+pub const NLM_F_EXCL: u16 = 0x200;
+pub const NLM_F_CREATE: u16 = 0x400;
+pub const NLM_F_APPEND: u16 = 0x800;
+
+pub const NLMSG_NOOP: u16 = 0x1;
+pub const NLMSG_ERROR: u16 = 0x2;
+pub const NLMSG_DONE: u16 = 0x3;
+
+pub const NLA_F_NESTED: u16 = 0x8000;
+pub const NLA_TYPE_MASK: u16 = 0x3fff;
+
+const NLMSG_HDRLEN: usize = 16;
+const NLA_HDRLEN: usize = 4;
+const RECV_BUF_SIZE: usize = 65536;
+
+pub fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+fn sys_err(code: i32) -> Error {
+    Error::Sys(Errno::from_i32(code))
+}
+
+/// A netlink request under construction: the message header, an optional
+/// fixed family header and a list of (possibly nested) attributes.
+#[derive(Clone, Debug)]
+pub struct Message {
+    buf: Vec<u8>,
+    nests: Vec<usize>,
+}
+
+impl Message {
+    pub fn new(ty: u16, flags: u16) -> Message {
+        let mut buf = vec![0u8; NLMSG_HDRLEN];
+        buf[4..6].copy_from_slice(&ty.to_ne_bytes());
+        buf[6..8].copy_from_slice(&(flags | NLM_F_REQUEST).to_ne_bytes());
+        Message {
+            buf,
+            nests: Vec::new(),
+        }
+    }
+
+    pub fn msg_type(&self) -> u16 {
+        u16::from_ne_bytes([self.buf[4], self.buf[5]])
+    }
+
+    pub fn flags(&self) -> u16 {
+        u16::from_ne_bytes([self.buf[6], self.buf[7]])
+    }
+
+    /// The length of the message on the wire, including padding.
+    pub fn size(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Appends raw bytes, such as a family specific header, padded to the
+    /// netlink alignment.
+    pub fn push(&mut self, data: &[u8]) -> &mut Message {
+        self.buf.extend_from_slice(data);
+        self.buf.resize(align(self.buf.len()), 0);
+        self
+    }
+
+    pub fn attr(&mut self, ty: u16, data: &[u8]) -> &mut Message {
+        let len = (NLA_HDRLEN + data.len()) as u16;
+        self.buf.extend_from_slice(&len.to_ne_bytes());
+        self.buf.extend_from_slice(&ty.to_ne_bytes());
+        self.push(data)
+    }
+
+    pub fn attr_u8(&mut self, ty: u16, v: u8) -> &mut Message {
+        self.attr(ty, &[v])
+    }
+
+    pub fn attr_u16(&mut self, ty: u16, v: u16) -> &mut Message {
+        self.attr(ty, &v.to_ne_bytes())
+    }
+
+    pub fn attr_u32(&mut self, ty: u16, v: u32) -> &mut Message {
+        self.attr(ty, &v.to_ne_bytes())
+    }
+
+    pub fn attr_u32_be(&mut self, ty: u16, v: u32) -> &mut Message {
+        self.attr(ty, &v.to_be_bytes())
+    }
+
+    pub fn attr_u64_be(&mut self, ty: u16, v: u64) -> &mut Message {
+        self.attr(ty, &v.to_be_bytes())
+    }
+
+    pub fn attr_str(&mut self, ty: u16, v: &str) -> &mut Message {
+        let mut data = Vec::with_capacity(v.len() + 1);
+        data.extend_from_slice(v.as_bytes());
+        data.push(0);
+        self.attr(ty, &data)
+    }
+
+    pub fn nest_start(&mut self, ty: u16) -> &mut Message {
+        self.nests.push(self.buf.len());
+        self.buf.extend_from_slice(&0u16.to_ne_bytes());
+        self.buf
+            .extend_from_slice(&(ty | NLA_F_NESTED).to_ne_bytes());
+        self
+    }
+
+    pub fn nest_end(&mut self) -> &mut Message {
+        if let Some(start) = self.nests.pop() {
+            let len = (self.buf.len() - start) as u16;
+            self.buf[start..start + 2].copy_from_slice(&len.to_ne_bytes());
+        }
+        self
+    }
+
+    pub(crate) fn finish(&mut self, seq: u32) -> &[u8] {
+        let len = self.buf.len() as u32;
+        self.buf[0..4].copy_from_slice(&len.to_ne_bytes());
+        self.buf[8..12].copy_from_slice(&seq.to_ne_bytes());
+        &self.buf
+    }
+}
+
+/// A received netlink message: header fields plus the payload following
+/// the netlink header.
+#[derive(Clone, Debug)]
+pub struct Reply {
+    pub ty: u16,
+    pub flags: u16,
+    pub seq: u32,
+    pub payload: Vec<u8>,
+}
+
+/// Iterates over the netlink messages packed in one datagram.
+pub fn messages(buf: &[u8]) -> Vec<Reply> {
+    let mut out = Vec::new();
+    let mut off = 0;
+    while off + NLMSG_HDRLEN <= buf.len() {
+        let len = u32::from_ne_bytes([buf[off], buf[off + 1], buf[off + 2], buf[off + 3]]) as usize;
+        if len < NLMSG_HDRLEN || off + len > buf.len() {
+            break;
+        }
+        out.push(Reply {
+            ty: u16::from_ne_bytes([buf[off + 4], buf[off + 5]]),
+            flags: u16::from_ne_bytes([buf[off + 6], buf[off + 7]]),
+            seq: u32::from_ne_bytes([buf[off + 8], buf[off + 9], buf[off + 10], buf[off + 11]]),
+            payload: buf[off + NLMSG_HDRLEN..off + len].to_vec(),
+        });
+        off += align(len);
+    }
+    out
+}
+
+/// Iterator over the attributes of a message payload or of a nested
+/// attribute. Yields the attribute type (without flag bits) and its data.
+pub struct Attrs<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Attrs<'a> {
+    pub fn new(buf: &'a [u8]) -> Attrs<'a> {
+        Attrs { buf }
+    }
+}
+
+impl<'a> Iterator for Attrs<'a> {
+    type Item = (u16, &'a [u8]);
+
+    fn next(&mut self) -> Option<(u16, &'a [u8])> {
+        if self.buf.len() < NLA_HDRLEN {
+            return None;
+        }
+        let len = u16::from_ne_bytes([self.buf[0], self.buf[1]]) as usize;
+        let ty = u16::from_ne_bytes([self.buf[2], self.buf[3]]) & NLA_TYPE_MASK;
+        if len < NLA_HDRLEN || len > self.buf.len() {
+            return None;
+        }
+        let data = &self.buf[NLA_HDRLEN..len];
+        let next = align(len).min(self.buf.len());
+        self.buf = &self.buf[next..];
+        Some((ty, data))
+    }
+}
+
+/// The address in network byte order, as attributes carry it.
+pub fn addr_bytes(addr: IpAddr) -> Vec<u8> {
+    match addr {
+        IpAddr::V4(a) => a.octets().to_vec(),
+        IpAddr::V6(a) => a.octets().to_vec(),
+    }
+}
+
+pub fn parse_u8(data: &[u8]) -> Option<u8> {
+    data.first().copied()
+}
+
+pub fn parse_u16(data: &[u8]) -> Option<u16> {
+    if data.len() < 2 {
+        return None;
+    }
+    Some(u16::from_ne_bytes([data[0], data[1]]))
+}
+
+pub fn parse_u32(data: &[u8]) -> Option<u32> {
+    if data.len() < 4 {
+        return None;
+    }
+    Some(u32::from_ne_bytes([data[0], data[1], data[2], data[3]]))
+}
+
+pub fn parse_i32(data: &[u8]) -> Option<i32> {
+    parse_u32(data).map(|v| v as i32)
+}
+
+pub fn parse_u32_be(data: &[u8]) -> Option<u32> {
+    parse_u32(data).map(u32::from_be)
+}
+
+pub fn parse_u64_be(data: &[u8]) -> Option<u64> {
+    if data.len() < 8 {
+        return None;
+    }
+    let mut b = [0u8; 8];
+    b.copy_from_slice(&data[..8]);
+    Some(u64::from_be_bytes(b))
+}
+
+pub fn parse_str(data: &[u8]) -> String {
+    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).into_owned()
+}
+
+/// A netlink socket. The socket belongs to the network namespace it was
+/// created in, see `Socket::new_in`.
+#[derive(Debug)]
+pub struct Socket {
+    fd: i32,
+    seq: u32,
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        let _ = close(self.fd);
+    }
+}
+
+impl Socket {
+    pub fn new(protocol: i32) -> errno::Result<Socket> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                protocol,
+            )
+        };
+        let fd = Errno::result(fd)?;
+        let sock = Socket { fd, seq: 0 };
+
+        let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as u16;
+        let res = unsafe {
+            libc::bind(
+                fd,
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as u32,
+            )
+        };
+        Errno::result(res)?;
+        Ok(sock)
+    }
+
+    pub fn new_in(ns: &NetNS, protocol: i32) -> errno::Result<Socket> {
+        ns.run(|| Socket::new(protocol))
+    }
+
+    pub fn fd(&self) -> i32 {
+        self.fd
+    }
+
+    fn next_seq(&mut self) -> u32 {
+        self.seq = self.seq.wrapping_add(1);
+        self.seq
+    }
+
+    /// Sends a group of messages in a single datagram and returns the
+    /// sequence numbers assigned to them.
+    pub fn send(&mut self, msgs: &mut [Message]) -> errno::Result<Vec<u32>> {
+        let mut buf = Vec::new();
+        let mut seqs = Vec::with_capacity(msgs.len());
+        for msg in msgs.iter_mut() {
+            let seq = self.next_seq();
+            seqs.push(seq);
+            buf.extend_from_slice(msg.finish(seq));
+        }
+
+        let res = unsafe { libc::send(self.fd, buf.as_ptr() as *const libc::c_void, buf.len(), 0) };
+        if res < 0 {
+            return Err(Error::Sys(Errno::last()));
+        }
+        Ok(seqs)
+    }
+
+    pub fn recv(&mut self, flags: i32) -> errno::Result<Vec<Reply>> {
+        let mut buf = vec![0u8; RECV_BUF_SIZE];
+        loop {
+            let res = unsafe {
+                libc::recv(
+                    self.fd,
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                    flags,
+                )
+            };
+            if res < 0 {
+                let e = Errno::last();
+                if e == Errno::from_i32(libc::EINTR) {
+                    continue;
+                }
+                return Err(Error::Sys(e));
+            }
+            return Ok(messages(&buf[..res as usize]));
+        }
+    }
+
+    /// Sends a request that expects an acknowledgement and waits for it.
+    pub fn request(&mut self, mut msg: Message) -> errno::Result<()> {
+        let flags = msg.flags() | NLM_F_ACK;
+        msg.buf[6..8].copy_from_slice(&flags.to_ne_bytes());
+        let seq = self.send(&mut [msg])?[0];
+        loop {
+            for reply in self.recv(0)? {
+                if reply.seq != seq {
+                    continue;
+                }
+                if reply.ty == NLMSG_ERROR {
+                    return check_ack(&reply);
+                }
+            }
+        }
+    }
+
+    /// Sends a request answered by a single message, such as a `RTM_GETLINK`
+    /// lookup by name, and returns that message.
+    pub fn query(&mut self, msg: Message) -> errno::Result<Reply> {
+        let seq = self.send(&mut [msg])?[0];
+        loop {
+            for reply in self.recv(0)? {
+                if reply.seq != seq {
+                    continue;
+                }
+                if reply.ty == NLMSG_ERROR {
+                    check_ack(&reply)?;
+                    return Err(sys_err(libc::ENOMSG));
+                }
+                return Ok(reply);
+            }
+        }
+    }
+
+    /// Sends a dump request and collects every reply until `NLMSG_DONE`.
+    pub fn dump(&mut self, msg: Message) -> errno::Result<Vec<Reply>> {
+        let seq = self.send(&mut [msg])?[0];
+        let mut out = Vec::new();
+        loop {
+            for reply in self.recv(0)? {
+                if reply.seq != seq {
+                    continue;
+                }
+                match reply.ty {
+                    NLMSG_DONE => return Ok(out),
+                    NLMSG_ERROR => {
+                        check_ack(&reply)?;
+                        return Ok(out);
+                    }
+                    NLMSG_NOOP => {}
+                    _ => out.push(reply),
+                }
+            }
+        }
+    }
+
+    /// Reads all replies already queued on the socket without blocking,
+    /// returning the first error reported by the kernel. Requests sent to
+    /// the kernel are processed synchronously, so once `send` returns
+    /// every acknowledgement and error is queued.
+    pub fn drain_acks(&mut self) -> errno::Result<()> {
+        let mut first_err = Ok(());
+        loop {
+            let replies = match self.recv(libc::MSG_DONTWAIT) {
+                Ok(v) => v,
+                Err(Error::Sys(e)) if e == Errno::from_i32(libc::EAGAIN) => return first_err,
+                Err(e) => return Err(e),
+            };
+            for reply in replies {
+                if reply.ty == NLMSG_ERROR && first_err.is_ok() {
+                    first_err = check_ack(&reply);
+                }
+            }
+        }
+    }
+
+    /// Grows the send buffer, if needed and as far as the kernel allows,
+    /// so a datagram of `size` bytes can be sent. Returns the largest
+    /// datagram `send` accepts afterwards.
+    pub fn reserve_send_buffer(&mut self, size: usize) -> errno::Result<usize> {
+        // The kernel doubles the requested value and keeps some of it for
+        // bookkeeping, see netlink_sendmsg().
+        const OVERHEAD: usize = 32;
+        let current = self.send_buffer()?;
+        if current >= size + OVERHEAD {
+            return Ok(current - OVERHEAD);
+        }
+        let want = ((size + OVERHEAD) / 2 + 1).min(i32::MAX as usize / 2) as libc::c_int;
+        for opt in [libc::SO_SNDBUFFORCE, libc::SO_SNDBUF] {
+            let res = unsafe {
+                libc::setsockopt(
+                    self.fd,
+                    libc::SOL_SOCKET,
+                    opt,
+                    &want as *const libc::c_int as *const libc::c_void,
+                    mem::size_of::<libc::c_int>() as u32,
+                )
+            };
+            // Without CAP_NET_ADMIN the forced variant fails and the
+            // plain one is capped at net.core.wmem_max.
+            if res == 0 {
+                break;
+            }
+        }
+        Ok(self.send_buffer()?.saturating_sub(OVERHEAD))
+    }
+
+    fn send_buffer(&self) -> errno::Result<usize> {
+        let mut size: libc::c_int = 0;
+        let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+        let res = unsafe {
+            libc::getsockopt(
+                self.fd,
+                libc::SOL_SOCKET,
+                libc::SO_SNDBUF,
+                &mut size as *mut libc::c_int as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        Errno::result(res)?;
+        Ok(size as usize)
+    }
+
+    pub fn add_membership(&mut self, group: u32) -> errno::Result<()> {
+        let res = unsafe {
+            libc::setsockopt(
+                self.fd,
+                libc::SOL_NETLINK,
+                libc::NETLINK_ADD_MEMBERSHIP,
+                &group as *const u32 as *const libc::c_void,
+                mem::size_of::<u32>() as u32,
+            )
+        };
+        Errno::result(res)?;
+        Ok(())
+    }
+}
+
+fn check_ack(reply: &Reply) -> errno::Result<()> {
+    match parse_i32(&reply.payload) {
+        Some(0) => Ok(()),
+        Some(code) => Err(sys_err(-code)),
+        None => Err(sys_err(libc::EBADMSG)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attrs_roundtrip() {
+        let mut msg = Message::new(16, 0);
+        msg.attr_str(1, "lo")
+            .nest_start(2)
+            .attr_u32(1, 7)
+            .attr_u8(2, 1)
+            .nest_end();
+        let buf = msg.finish(1).to_vec();
+
+        let replies = messages(&buf);
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].ty, 16);
+        assert_eq!(replies[0].seq, 1);
+
+        let attrs: Vec<_> = Attrs::new(&replies[0].payload).collect();
+        assert_eq!(attrs.len(), 2);
+        assert_eq!(parse_str(attrs[0].1), "lo");
+        assert_eq!(attrs[1].0, 2);
+
+        let nested: Vec<_> = Attrs::new(attrs[1].1).collect();
+        assert_eq!(parse_u32(nested[0].1), Some(7));
+        assert_eq!(parse_u8(nested[1].1), Some(1));
+    }
+}
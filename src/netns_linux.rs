@@ -1,6 +1,20 @@
+use std::ffi::{CStr, CString};
+use std::fs::File;
+use std::mem;
+use std::os::unix::io::{FromRawFd, OwnedFd};
+use std::os::unix::process::ExitStatusExt;
+use std::panic;
+use std::process::ExitStatus;
+use std::ptr;
+use std::thread;
+
 use super::errno;
+use super::namespace::{Namespace, NsType};
 use libc::{c_char, c_uint};
 
+pub const CLONE_NEWTIME: i32 = 0x00000080;
+pub const CLONE_NEWNS: i32 = 0x00020000;
+pub const CLONE_NEWCGROUP: i32 = 0x02000000;
 pub const CLONE_NEWUTS: i32 = 0x04000000;
 pub const CLONE_NEWIPC: i32 = 0x08000000;
 pub const CLONE_NEWUSER: i32 = 0x10000000;
@@ -12,6 +26,11 @@ pub const O_WRONLY: i32 = 0x000000001;
 pub const O_RDWR: i32 = 0x000000002;
 pub const O_CREAT: i32 = 0x000000100;
 
+/// Where `ip netns add` bind mounts named namespaces.
+pub const NETNS_RUN_DIR: &str = "/run/netns";
+
+const SIOCGSKNS: u64 = 0x894C;
+
 pub fn gettid() -> i32 {
     unsafe { libc::syscall(libc::SYS_gettid) as i32 }
 }
@@ -35,68 +54,243 @@ pub fn close(fd: i32) -> errno::Result<i32> {
     errno::Errno::result(res)
 }
 
-pub fn open(path: String, oflag: i32, mode: i32) -> errno::Result<i32> {
-    let fd = unsafe { libc::open(path.as_ptr() as *const c_char, oflag, mode as c_uint) };
-    errno::Errno::result(fd)
+pub(crate) fn pipe() -> errno::Result<(File, File)> {
+    let mut fds = [0; 2];
+    let res = unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) };
+    errno::Errno::result(res)?;
+    Ok(unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) })
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
-pub struct NetNS {
-    fd: i32,
-    path: String,
+pub(crate) fn waitpid(pid: i32) -> errno::Result<ExitStatus> {
+    let mut status = 0;
+    loop {
+        let res = unsafe { libc::waitpid(pid, &mut status, 0) };
+        match errno::Errno::result(res) {
+            Ok(_) => return Ok(ExitStatus::from_raw(status)),
+            Err(errno::Error::Sys(e)) if e == errno::Errno::from_i32(libc::EINTR) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+pub(crate) fn pidfd_open(pid: i32) -> errno::Result<OwnedFd> {
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+    let fd = errno::Errno::result(fd)?;
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as i32) })
 }
 
-impl Drop for NetNS {
-    fn drop(&mut self) {
-        let _ = close(self.fd);
+pub(crate) fn pidfd_send_signal(pidfd: i32, signal: i32) -> errno::Result<()> {
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_pidfd_send_signal,
+            pidfd,
+            signal,
+            ptr::null::<libc::siginfo_t>(),
+            0,
+        )
+    };
+    errno::Errno::result(res)?;
+    Ok(())
+}
+
+// Waits for a scoped helper thread, passing a panic on to the caller.
+pub(crate) fn join_helper<T>(handle: thread::ScopedJoinHandle<'_, T>) -> T {
+    match handle.join() {
+        Ok(v) => v,
+        Err(e) => panic::resume_unwind(e),
     }
 }
 
+// Runs `f` on a short-lived thread and returns its result. Namespaces `f`
+// joins or creates end with that thread, so the caller never moves.
+pub(crate) fn on_helper_thread<F, T>(f: F) -> T
+where
+    F: FnOnce() -> T + Send,
+    T: Send,
+{
+    thread::scope(|s| join_helper(s.spawn(f)))
+}
+
+// struct clone_args up to CLONE_ARGS_SIZE_VER0.
+#[repr(C)]
+#[derive(Default)]
+struct CloneArgs {
+    flags: u64,
+    pidfd: u64,
+    child_tid: u64,
+    parent_tid: u64,
+    exit_signal: u64,
+    stack: u64,
+    stack_size: u64,
+    tls: u64,
+}
+
+/// Forks like fork(2) with additional clone `flags`. Returns 0 in the
+/// child and the child's pid in the parent.
+///
+/// # Safety
+///
+/// The child only has a copy of the calling thread. Until it execs or
+/// calls `_exit` it must stick to async-signal-safe calls: no allocation,
+/// no locking (including stdio), no panicking and no returning into code
+/// that might do any of these.
+pub(crate) unsafe fn clone3(flags: i32, exit_signal: i32) -> errno::Result<i32> {
+    let args = CloneArgs {
+        flags: flags as u64,
+        exit_signal: exit_signal as u64,
+        ..Default::default()
+    };
+    let res = libc::syscall(
+        libc::SYS_clone3,
+        &args as *const CloneArgs,
+        mem::size_of::<CloneArgs>(),
+    );
+    Ok(errno::Errno::result(res)? as i32)
+}
+
+pub fn fstat(fd: i32) -> errno::Result<libc::stat> {
+    let mut st: libc::stat = unsafe { std::mem::zeroed() };
+    let res = unsafe { libc::fstat(fd, &mut st) };
+    errno::Errno::result(res)?;
+    Ok(st)
+}
+
+pub fn mount(
+    source: Option<&CStr>,
+    target: &CStr,
+    fstype: Option<&CStr>,
+    flags: libc::c_ulong,
+    data: Option<&CStr>,
+) -> errno::Result<()> {
+    let ptr_of = |s: Option<&CStr>| s.map_or(ptr::null(), |s| s.as_ptr());
+    let res = unsafe {
+        libc::mount(
+            ptr_of(source),
+            target.as_ptr(),
+            ptr_of(fstype),
+            flags,
+            ptr_of(data) as *const libc::c_void,
+        )
+    };
+    errno::Errno::result(res)?;
+    Ok(())
+}
+
+pub fn umount2(target: &CStr, flags: i32) -> errno::Result<()> {
+    let res = unsafe { libc::umount2(target.as_ptr(), flags) };
+    errno::Errno::result(res)?;
+    Ok(())
+}
+
+pub fn open(path: String, oflag: i32, mode: i32) -> errno::Result<i32> {
+    let cpath = match CString::new(path) {
+        Ok(v) => v,
+        Err(_) => return Err(errno::Error::Sys(errno::Errno::from_i32(libc::EINVAL))),
+    };
+    let fd = unsafe { libc::open(cpath.as_ptr() as *const c_char, oflag, mode as c_uint) };
+    errno::Errno::result(fd)
+}
+
+/// A handle to a network namespace. It owns its file descriptor, so use
+/// `try_clone` for a second handle and `same_as` to compare namespaces.
+#[derive(Debug)]
+pub struct NetNS {
+    ns: Namespace,
+}
+
 impl NetNS {
     #[allow(dead_code)]
     pub fn new() -> errno::Result<NetNS> {
-        match unshare(CLONE_NEWNET) {
-            Ok(_v) => {}
-            Err(e) => {
-                return Err(e);
-            }
-        }
+        unshare(CLONE_NEWNET)?;
         NetNS::get()
     }
 
     pub fn get() -> errno::Result<NetNS> {
-        return NetNS::get_from_thread(getpid(), gettid());
+        NetNS::get_from_thread(getpid(), gettid())
     }
 
     pub fn set(ns: &NetNS) -> Result<(), errno::Error> {
-        match setns(ns.fd, CLONE_NEWNET) {
-            Ok(_v) => Ok(()),
-            Err(e) => {
-                return Err(e);
-            }
-        }
+        Namespace::set(&ns.ns)
     }
 
     pub fn get_from_thread(pid: i32, tid: i32) -> errno::Result<NetNS> {
-        return NetNS::get_from_path(&format!("/proc/{}/task/{}/ns/net", pid, tid));
+        NetNS::get_from_path(&format!("/proc/{}/task/{}/ns/net", pid, tid))
     }
 
     pub fn get_from_process(pid: i32) -> errno::Result<NetNS> {
-        return NetNS::get_from_path(&format!("/proc/{}/ns/net", pid));
-    }
-
-    pub fn get_from_path(path: &String) -> errno::Result<NetNS> {
-        let fd = open(path.clone(), O_RDONLY, 0);
-        match fd {
-            Ok(v) => {
-                return Ok(NetNS {
-                    fd: v,
-                    path: path.clone(),
-                });
-            }
-            Err(e) => {
-                return Err(e);
-            }
+        NetNS::get_from_path(&format!("/proc/{}/ns/net", pid))
+    }
+
+    pub fn get_from_path(path: &str) -> errno::Result<NetNS> {
+        Ok(NetNS {
+            ns: Namespace::get_from_path(path, NsType::Net)?,
+        })
+    }
+
+    /// Opens a namespace created by `ip netns add`.
+    pub fn get_from_name(name: &str) -> errno::Result<NetNS> {
+        if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+            return Err(errno::Error::Sys(errno::Errno::from_i32(libc::EINVAL)));
+        }
+        NetNS::get_from_path(&format!("{}/{}", NETNS_RUN_DIR, name))
+    }
+
+    /// Returns the namespace a socket lives in, which is the namespace it
+    /// was created in rather than that of the calling thread.
+    pub fn from_socket(sock: i32) -> errno::Result<NetNS> {
+        let fd = errno::Errno::result(unsafe { libc::ioctl(sock, SIOCGSKNS as _) })?;
+        Ok(NetNS {
+            ns: Namespace::from_fd(fd, NsType::Net),
+        })
+    }
+
+    pub(crate) fn into_fd_path(self) -> NetNS {
+        NetNS {
+            ns: self.ns.into_fd_path(),
         }
     }
+
+    /// The nsfs inode number identifying this namespace, as shown by
+    /// `readlink /proc/<pid>/ns/net`.
+    pub fn inode(&self) -> errno::Result<u64> {
+        self.ns.inode()
+    }
+
+    /// Whether both handles refer to the same namespace, regardless of how
+    /// they were opened.
+    pub fn same_as(&self, other: &NetNS) -> errno::Result<bool> {
+        self.ns.same_as(&other.ns)
+    }
+
+    /// Returns a new handle to the same namespace backed by its own file
+    /// descriptor.
+    pub fn try_clone(&self) -> errno::Result<NetNS> {
+        Ok(NetNS {
+            ns: self.ns.try_clone()?,
+        })
+    }
+
+    pub fn fd(&self) -> i32 {
+        self.ns.fd()
+    }
+
+    pub fn path(&self) -> &str {
+        self.ns.path()
+    }
+
+    /// The generic handle this one wraps.
+    pub fn namespace(&self) -> &Namespace {
+        &self.ns
+    }
+
+    /// Runs `f` on a short-lived thread that has joined this namespace, so
+    /// the calling thread never changes namespace. Sockets created by `f`
+    /// stay bound to this namespace after the thread exits.
+    pub fn run<F, T>(&self, f: F) -> errno::Result<T>
+    where
+        F: FnOnce() -> errno::Result<T> + Send,
+        T: Send,
+    {
+        self.ns.run(f)
+    }
 }
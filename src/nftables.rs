@@ -0,0 +1,1085 @@
+use std::net::IpAddr;
+
+use super::errno::{self, Errno, Error};
+use super::netlink::{self, Attrs, Message, Reply, Socket};
+use super::netns_linux::NetNS;
+
+const NFNL_SUBSYS_NFTABLES: u16 = 10;
+const NFNL_MSG_BATCH_BEGIN: u16 = 0x10;
+const NFNL_MSG_BATCH_END: u16 = 0x11;
+const NFNETLINK_V0: u8 = 0;
+
+const NFT_MSG_NEWTABLE: u16 = 0;
+const NFT_MSG_GETTABLE: u16 = 1;
+const NFT_MSG_DELTABLE: u16 = 2;
+const NFT_MSG_NEWCHAIN: u16 = 3;
+const NFT_MSG_GETCHAIN: u16 = 4;
+const NFT_MSG_DELCHAIN: u16 = 5;
+const NFT_MSG_NEWRULE: u16 = 6;
+const NFT_MSG_GETRULE: u16 = 7;
+const NFT_MSG_DELRULE: u16 = 8;
+
+const NFTA_TABLE_NAME: u16 = 1;
+const NFTA_TABLE_HANDLE: u16 = 4;
+
+const NFTA_CHAIN_TABLE: u16 = 1;
+const NFTA_CHAIN_HANDLE: u16 = 2;
+const NFTA_CHAIN_NAME: u16 = 3;
+const NFTA_CHAIN_HOOK: u16 = 4;
+const NFTA_CHAIN_POLICY: u16 = 5;
+const NFTA_CHAIN_TYPE: u16 = 7;
+
+const NFTA_HOOK_HOOKNUM: u16 = 1;
+const NFTA_HOOK_PRIORITY: u16 = 2;
+
+const NFTA_RULE_TABLE: u16 = 1;
+const NFTA_RULE_CHAIN: u16 = 2;
+const NFTA_RULE_HANDLE: u16 = 3;
+const NFTA_RULE_EXPRESSIONS: u16 = 4;
+
+const NFTA_LIST_ELEM: u16 = 1;
+const NFTA_EXPR_NAME: u16 = 1;
+const NFTA_EXPR_DATA: u16 = 2;
+
+const NFTA_DATA_VALUE: u16 = 1;
+const NFTA_DATA_VERDICT: u16 = 2;
+const NFTA_VERDICT_CODE: u16 = 1;
+
+const NFTA_META_DREG: u16 = 1;
+const NFTA_META_KEY: u16 = 2;
+const NFT_META_IIFNAME: u32 = 6;
+const NFT_META_OIFNAME: u32 = 7;
+const NFT_META_NFPROTO: u32 = 15;
+const NFT_META_L4PROTO: u32 = 16;
+
+const NFTA_CMP_SREG: u16 = 1;
+const NFTA_CMP_OP: u16 = 2;
+const NFTA_CMP_DATA: u16 = 3;
+const NFT_CMP_EQ: u32 = 0;
+
+const NFTA_PAYLOAD_DREG: u16 = 1;
+const NFTA_PAYLOAD_BASE: u16 = 2;
+const NFTA_PAYLOAD_OFFSET: u16 = 3;
+const NFTA_PAYLOAD_LEN: u16 = 4;
+const NFT_PAYLOAD_NETWORK_HEADER: u32 = 1;
+const NFT_PAYLOAD_TRANSPORT_HEADER: u32 = 2;
+
+const NFTA_BITWISE_SREG: u16 = 1;
+const NFTA_BITWISE_DREG: u16 = 2;
+const NFTA_BITWISE_LEN: u16 = 3;
+const NFTA_BITWISE_MASK: u16 = 4;
+const NFTA_BITWISE_XOR: u16 = 5;
+
+const NFTA_IMMEDIATE_DREG: u16 = 1;
+const NFTA_IMMEDIATE_DATA: u16 = 2;
+
+const NFTA_NAT_TYPE: u16 = 1;
+const NFTA_NAT_FAMILY: u16 = 2;
+const NFTA_NAT_REG_ADDR_MIN: u16 = 3;
+const NFTA_NAT_REG_PROTO_MIN: u16 = 5;
+const NFT_NAT_SNAT: u32 = 0;
+const NFT_NAT_DNAT: u32 = 1;
+
+const NFTA_FIB_DREG: u16 = 1;
+const NFTA_FIB_RESULT: u16 = 2;
+const NFTA_FIB_FLAGS: u16 = 3;
+const NFT_FIB_RESULT_ADDRTYPE: u32 = 3;
+const NFTA_FIB_F_DADDR: u32 = 1 << 1;
+const RTN_LOCAL: u32 = 2;
+
+const NFT_REG_VERDICT: u32 = 0;
+const NFT_REG_1: u32 = 1;
+const NFT_REG_2: u32 = 2;
+
+const NF_DROP: u32 = 0;
+const NF_ACCEPT: u32 = 1;
+
+const NFPROTO_UNSPEC: u8 = 0;
+const NFPROTO_INET: u8 = 1;
+const NFPROTO_IPV4: u8 = 2;
+const NFPROTO_ARP: u8 = 3;
+const NFPROTO_NETDEV: u8 = 5;
+const NFPROTO_BRIDGE: u8 = 7;
+const NFPROTO_IPV6: u8 = 10;
+
+const IFNAMSIZ: usize = 16;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Family {
+    Inet,
+    Ip,
+    Ip6,
+    Arp,
+    Bridge,
+    Netdev,
+}
+
+impl Family {
+    fn raw(self) -> u8 {
+        match self {
+            Family::Inet => NFPROTO_INET,
+            Family::Ip => NFPROTO_IPV4,
+            Family::Ip6 => NFPROTO_IPV6,
+            Family::Arp => NFPROTO_ARP,
+            Family::Bridge => NFPROTO_BRIDGE,
+            Family::Netdev => NFPROTO_NETDEV,
+        }
+    }
+
+    fn from_raw(v: u8) -> Option<Family> {
+        match v {
+            NFPROTO_INET => Some(Family::Inet),
+            NFPROTO_IPV4 => Some(Family::Ip),
+            NFPROTO_IPV6 => Some(Family::Ip6),
+            NFPROTO_ARP => Some(Family::Arp),
+            NFPROTO_BRIDGE => Some(Family::Bridge),
+            NFPROTO_NETDEV => Some(Family::Netdev),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Hook {
+    Prerouting,
+    Input,
+    Forward,
+    Output,
+    Postrouting,
+}
+
+impl Hook {
+    fn raw(self) -> u32 {
+        match self {
+            Hook::Prerouting => 0,
+            Hook::Input => 1,
+            Hook::Forward => 2,
+            Hook::Output => 3,
+            Hook::Postrouting => 4,
+        }
+    }
+
+    fn from_raw(v: u32) -> Option<Hook> {
+        match v {
+            0 => Some(Hook::Prerouting),
+            1 => Some(Hook::Input),
+            2 => Some(Hook::Forward),
+            3 => Some(Hook::Output),
+            4 => Some(Hook::Postrouting),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ChainType {
+    Filter,
+    Nat,
+    Route,
+}
+
+impl ChainType {
+    fn name(self) -> &'static str {
+        match self {
+            ChainType::Filter => "filter",
+            ChainType::Nat => "nat",
+            ChainType::Route => "route",
+        }
+    }
+
+    fn from_name(v: &str) -> Option<ChainType> {
+        match v {
+            "filter" => Some(ChainType::Filter),
+            "nat" => Some(ChainType::Nat),
+            "route" => Some(ChainType::Route),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Policy {
+    Accept,
+    Drop,
+}
+
+impl Policy {
+    fn raw(self) -> u32 {
+        match self {
+            Policy::Accept => NF_ACCEPT,
+            Policy::Drop => NF_DROP,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+impl Protocol {
+    fn raw(self) -> u8 {
+        match self {
+            Protocol::Tcp => libc::IPPROTO_TCP as u8,
+            Protocol::Udp => libc::IPPROTO_UDP as u8,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Table {
+    pub family: Family,
+    pub name: String,
+    pub handle: Option<u64>,
+}
+
+impl Table {
+    pub fn new(family: Family, name: &str) -> Table {
+        Table {
+            family,
+            name: name.to_string(),
+            handle: None,
+        }
+    }
+}
+
+/// Hook registration of a base chain. Chains without one are regular
+/// chains that can only be reached by jumping to them.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct BaseChain {
+    pub ty: ChainType,
+    pub hook: Hook,
+    pub priority: i32,
+    pub policy: Policy,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Chain {
+    pub family: Family,
+    pub table: String,
+    pub name: String,
+    pub base: Option<BaseChain>,
+    pub handle: Option<u64>,
+}
+
+impl Chain {
+    pub fn new(family: Family, table: &str, name: &str) -> Chain {
+        Chain {
+            family,
+            table: table.to_string(),
+            name: name.to_string(),
+            base: None,
+            handle: None,
+        }
+    }
+
+    pub fn hook(mut self, ty: ChainType, hook: Hook, priority: i32) -> Chain {
+        self.base = Some(BaseChain {
+            ty,
+            hook,
+            priority,
+            policy: Policy::Accept,
+        });
+        self
+    }
+
+    pub fn policy(mut self, policy: Policy) -> Chain {
+        if let Some(base) = self.base.as_mut() {
+            base.policy = policy;
+        }
+        self
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Expr {
+    Meta {
+        key: u32,
+        dreg: u32,
+    },
+    Cmp {
+        sreg: u32,
+        data: Vec<u8>,
+    },
+    Payload {
+        base: u32,
+        offset: u32,
+        len: u32,
+        dreg: u32,
+    },
+    Bitwise {
+        reg: u32,
+        mask: Vec<u8>,
+    },
+    Immediate {
+        dreg: u32,
+        data: Vec<u8>,
+    },
+    Verdict(u32),
+    Fib {
+        result: u32,
+        flags: u32,
+        dreg: u32,
+    },
+    Masq,
+    Nat {
+        ty: u32,
+        family: u8,
+        port: bool,
+    },
+}
+
+/// A rule built from matches followed by a verdict or NAT statement, e.g.
+/// `Rule::new(Family::Ip, "nat", "post").saddr(net, 24).masquerade()`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Rule {
+    pub family: Family,
+    pub table: String,
+    pub chain: String,
+    pub handle: Option<u64>,
+    exprs: Vec<Expr>,
+}
+
+fn prefix_mask(len: usize, prefix: u8) -> Vec<u8> {
+    let mut mask = vec![0u8; len];
+    let mut bits = prefix as usize;
+    for b in mask.iter_mut() {
+        if bits >= 8 {
+            *b = 0xff;
+            bits -= 8;
+        } else {
+            *b = !(0xffu8 >> bits);
+            bits = 0;
+        }
+    }
+    mask
+}
+
+fn nfproto_of(addr: IpAddr) -> u8 {
+    match addr {
+        IpAddr::V4(_) => NFPROTO_IPV4,
+        IpAddr::V6(_) => NFPROTO_IPV6,
+    }
+}
+
+impl Rule {
+    pub fn new(family: Family, table: &str, chain: &str) -> Rule {
+        Rule {
+            family,
+            table: table.to_string(),
+            chain: chain.to_string(),
+            handle: None,
+            exprs: Vec::new(),
+        }
+    }
+
+    fn cmp_meta(mut self, key: u32, data: Vec<u8>) -> Rule {
+        self.exprs.push(Expr::Meta {
+            key,
+            dreg: NFT_REG_1,
+        });
+        self.exprs.push(Expr::Cmp {
+            sreg: NFT_REG_1,
+            data,
+        });
+        self
+    }
+
+    fn addr(mut self, addr: IpAddr, prefix: u8, source: bool) -> Rule {
+        if self.family == Family::Inet {
+            self = self.cmp_meta(NFT_META_NFPROTO, vec![nfproto_of(addr)]);
+        }
+        let (offset, len) = match (addr, source) {
+            (IpAddr::V4(_), true) => (12, 4),
+            (IpAddr::V4(_), false) => (16, 4),
+            (IpAddr::V6(_), true) => (8, 16),
+            (IpAddr::V6(_), false) => (24, 16),
+        };
+        self.exprs.push(Expr::Payload {
+            base: NFT_PAYLOAD_NETWORK_HEADER,
+            offset,
+            len,
+            dreg: NFT_REG_1,
+        });
+
+        let mut data = netlink::addr_bytes(addr);
+        if (prefix as usize) < len as usize * 8 {
+            let mask = prefix_mask(len as usize, prefix);
+            for (d, m) in data.iter_mut().zip(mask.iter()) {
+                *d &= m;
+            }
+            self.exprs.push(Expr::Bitwise {
+                reg: NFT_REG_1,
+                mask,
+            });
+        }
+        self.exprs.push(Expr::Cmp {
+            sreg: NFT_REG_1,
+            data,
+        });
+        self
+    }
+
+    pub fn saddr(self, addr: IpAddr, prefix: u8) -> Rule {
+        self.addr(addr, prefix, true)
+    }
+
+    pub fn daddr(self, addr: IpAddr, prefix: u8) -> Rule {
+        self.addr(addr, prefix, false)
+    }
+
+    pub fn protocol(self, proto: Protocol) -> Rule {
+        self.cmp_meta(NFT_META_L4PROTO, vec![proto.raw()])
+    }
+
+    fn port(mut self, proto: Protocol, port: u16, offset: u32) -> Rule {
+        self = self.protocol(proto);
+        self.exprs.push(Expr::Payload {
+            base: NFT_PAYLOAD_TRANSPORT_HEADER,
+            offset,
+            len: 2,
+            dreg: NFT_REG_1,
+        });
+        self.exprs.push(Expr::Cmp {
+            sreg: NFT_REG_1,
+            data: port.to_be_bytes().to_vec(),
+        });
+        self
+    }
+
+    pub fn sport(self, proto: Protocol, port: u16) -> Rule {
+        self.port(proto, port, 0)
+    }
+
+    pub fn dport(self, proto: Protocol, port: u16) -> Rule {
+        self.port(proto, port, 2)
+    }
+
+    fn ifname(name: &str) -> Vec<u8> {
+        let mut data = name.as_bytes().to_vec();
+        data.truncate(IFNAMSIZ - 1);
+        data.push(0);
+        data
+    }
+
+    pub fn iifname(self, name: &str) -> Rule {
+        self.cmp_meta(NFT_META_IIFNAME, Rule::ifname(name))
+    }
+
+    pub fn oifname(self, name: &str) -> Rule {
+        self.cmp_meta(NFT_META_OIFNAME, Rule::ifname(name))
+    }
+
+    /// Matches packets whose destination address is local to the host,
+    /// like `fib daddr type local`.
+    pub fn daddr_local(mut self) -> Rule {
+        self.exprs.push(Expr::Fib {
+            result: NFT_FIB_RESULT_ADDRTYPE,
+            flags: NFTA_FIB_F_DADDR,
+            dreg: NFT_REG_1,
+        });
+        self.exprs.push(Expr::Cmp {
+            sreg: NFT_REG_1,
+            data: RTN_LOCAL.to_ne_bytes().to_vec(),
+        });
+        self
+    }
+
+    pub fn accept(mut self) -> Rule {
+        self.exprs.push(Expr::Verdict(NF_ACCEPT));
+        self
+    }
+
+    pub fn drop(mut self) -> Rule {
+        self.exprs.push(Expr::Verdict(NF_DROP));
+        self
+    }
+
+    pub fn masquerade(mut self) -> Rule {
+        self.exprs.push(Expr::Masq);
+        self
+    }
+
+    fn nat(mut self, ty: u32, addr: IpAddr, port: Option<u16>) -> Rule {
+        self.exprs.push(Expr::Immediate {
+            dreg: NFT_REG_1,
+            data: netlink::addr_bytes(addr),
+        });
+        if let Some(p) = port {
+            self.exprs.push(Expr::Immediate {
+                dreg: NFT_REG_2,
+                data: p.to_be_bytes().to_vec(),
+            });
+        }
+        self.exprs.push(Expr::Nat {
+            ty,
+            family: nfproto_of(addr),
+            port: port.is_some(),
+        });
+        self
+    }
+
+    pub fn dnat(self, addr: IpAddr, port: Option<u16>) -> Rule {
+        self.nat(NFT_NAT_DNAT, addr, port)
+    }
+
+    pub fn snat(self, addr: IpAddr, port: Option<u16>) -> Rule {
+        self.nat(NFT_NAT_SNAT, addr, port)
+    }
+}
+
+fn put_data(msg: &mut Message, ty: u16, data: &[u8]) {
+    msg.nest_start(ty).attr(NFTA_DATA_VALUE, data).nest_end();
+}
+
+fn put_expr(msg: &mut Message, expr: &Expr) {
+    let name = match expr {
+        Expr::Meta { .. } => "meta",
+        Expr::Cmp { .. } => "cmp",
+        Expr::Payload { .. } => "payload",
+        Expr::Bitwise { .. } => "bitwise",
+        Expr::Immediate { .. } | Expr::Verdict(_) => "immediate",
+        Expr::Fib { .. } => "fib",
+        Expr::Masq => "masq",
+        Expr::Nat { .. } => "nat",
+    };
+    msg.nest_start(NFTA_LIST_ELEM)
+        .attr_str(NFTA_EXPR_NAME, name);
+    msg.nest_start(NFTA_EXPR_DATA);
+    match expr {
+        Expr::Meta { key, dreg } => {
+            msg.attr_u32_be(NFTA_META_KEY, *key)
+                .attr_u32_be(NFTA_META_DREG, *dreg);
+        }
+        Expr::Cmp { sreg, data } => {
+            msg.attr_u32_be(NFTA_CMP_SREG, *sreg)
+                .attr_u32_be(NFTA_CMP_OP, NFT_CMP_EQ);
+            put_data(msg, NFTA_CMP_DATA, data);
+        }
+        Expr::Payload {
+            base,
+            offset,
+            len,
+            dreg,
+        } => {
+            msg.attr_u32_be(NFTA_PAYLOAD_DREG, *dreg)
+                .attr_u32_be(NFTA_PAYLOAD_BASE, *base)
+                .attr_u32_be(NFTA_PAYLOAD_OFFSET, *offset)
+                .attr_u32_be(NFTA_PAYLOAD_LEN, *len);
+        }
+        Expr::Bitwise { reg, mask } => {
+            msg.attr_u32_be(NFTA_BITWISE_SREG, *reg)
+                .attr_u32_be(NFTA_BITWISE_DREG, *reg)
+                .attr_u32_be(NFTA_BITWISE_LEN, mask.len() as u32);
+            put_data(msg, NFTA_BITWISE_MASK, mask);
+            put_data(msg, NFTA_BITWISE_XOR, &vec![0u8; mask.len()]);
+        }
+        Expr::Immediate { dreg, data } => {
+            msg.attr_u32_be(NFTA_IMMEDIATE_DREG, *dreg);
+            put_data(msg, NFTA_IMMEDIATE_DATA, data);
+        }
+        Expr::Verdict(code) => {
+            msg.attr_u32_be(NFTA_IMMEDIATE_DREG, NFT_REG_VERDICT)
+                .nest_start(NFTA_IMMEDIATE_DATA)
+                .nest_start(NFTA_DATA_VERDICT)
+                .attr_u32_be(NFTA_VERDICT_CODE, *code)
+                .nest_end()
+                .nest_end();
+        }
+        Expr::Fib {
+            result,
+            flags,
+            dreg,
+        } => {
+            msg.attr_u32_be(NFTA_FIB_DREG, *dreg)
+                .attr_u32_be(NFTA_FIB_RESULT, *result)
+                .attr_u32_be(NFTA_FIB_FLAGS, *flags);
+        }
+        Expr::Masq => {}
+        Expr::Nat { ty, family, port } => {
+            msg.attr_u32_be(NFTA_NAT_TYPE, *ty)
+                .attr_u32_be(NFTA_NAT_FAMILY, *family as u32)
+                .attr_u32_be(NFTA_NAT_REG_ADDR_MIN, NFT_REG_1);
+            if *port {
+                msg.attr_u32_be(NFTA_NAT_REG_PROTO_MIN, NFT_REG_2);
+            }
+        }
+    }
+    msg.nest_end().nest_end();
+}
+
+fn nft_msg(ty: u16, family: u8, flags: u16) -> Message {
+    let mut msg = Message::new((NFNL_SUBSYS_NFTABLES << 8) | ty, flags);
+    msg.push(&[family, NFNETLINK_V0, 0, 0]);
+    msg
+}
+
+fn batch_msg(ty: u16) -> Message {
+    let mut msg = Message::new(ty, 0);
+    let res_id = NFNL_SUBSYS_NFTABLES.to_be_bytes();
+    msg.push(&[NFPROTO_UNSPEC, NFNETLINK_V0, res_id[0], res_id[1]]);
+    msg
+}
+
+/// A set of changes applied atomically by `Nftables::apply`, as long as
+/// it fits in one netlink datagram.
+#[derive(Clone, Debug, Default)]
+pub struct Batch {
+    msgs: Vec<Message>,
+}
+
+impl Batch {
+    pub fn new() -> Batch {
+        Batch::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.msgs.is_empty()
+    }
+
+    pub fn add_table(&mut self, table: &Table) -> &mut Batch {
+        let mut msg = nft_msg(NFT_MSG_NEWTABLE, table.family.raw(), netlink::NLM_F_CREATE);
+        msg.attr_str(NFTA_TABLE_NAME, &table.name);
+        self.msgs.push(msg);
+        self
+    }
+
+    pub fn delete_table(&mut self, table: &Table) -> &mut Batch {
+        let mut msg = nft_msg(NFT_MSG_DELTABLE, table.family.raw(), 0);
+        msg.attr_str(NFTA_TABLE_NAME, &table.name);
+        self.msgs.push(msg);
+        self
+    }
+
+    pub fn add_chain(&mut self, chain: &Chain) -> &mut Batch {
+        let mut msg = nft_msg(NFT_MSG_NEWCHAIN, chain.family.raw(), netlink::NLM_F_CREATE);
+        msg.attr_str(NFTA_CHAIN_TABLE, &chain.table)
+            .attr_str(NFTA_CHAIN_NAME, &chain.name);
+        if let Some(base) = chain.base {
+            msg.nest_start(NFTA_CHAIN_HOOK)
+                .attr_u32_be(NFTA_HOOK_HOOKNUM, base.hook.raw())
+                .attr_u32_be(NFTA_HOOK_PRIORITY, base.priority as u32)
+                .nest_end()
+                .attr_u32_be(NFTA_CHAIN_POLICY, base.policy.raw())
+                .attr_str(NFTA_CHAIN_TYPE, base.ty.name());
+        }
+        self.msgs.push(msg);
+        self
+    }
+
+    pub fn delete_chain(&mut self, chain: &Chain) -> &mut Batch {
+        let mut msg = nft_msg(NFT_MSG_DELCHAIN, chain.family.raw(), 0);
+        msg.attr_str(NFTA_CHAIN_TABLE, &chain.table)
+            .attr_str(NFTA_CHAIN_NAME, &chain.name);
+        self.msgs.push(msg);
+        self
+    }
+
+    pub fn add_rule(&mut self, rule: &Rule) -> &mut Batch {
+        let mut msg = nft_msg(
+            NFT_MSG_NEWRULE,
+            rule.family.raw(),
+            netlink::NLM_F_CREATE | netlink::NLM_F_APPEND,
+        );
+        msg.attr_str(NFTA_RULE_TABLE, &rule.table)
+            .attr_str(NFTA_RULE_CHAIN, &rule.chain);
+        msg.nest_start(NFTA_RULE_EXPRESSIONS);
+        for expr in rule.exprs.iter() {
+            put_expr(&mut msg, expr);
+        }
+        msg.nest_end();
+        self.msgs.push(msg);
+        self
+    }
+
+    /// Deletes the rule identified by `rule.handle`, as returned by
+    /// `Nftables::list_rules`.
+    pub fn delete_rule(&mut self, rule: &Rule) -> &mut Batch {
+        let mut msg = nft_msg(NFT_MSG_DELRULE, rule.family.raw(), 0);
+        msg.attr_str(NFTA_RULE_TABLE, &rule.table)
+            .attr_str(NFTA_RULE_CHAIN, &rule.chain);
+        if let Some(handle) = rule.handle {
+            msg.attr_u64_be(NFTA_RULE_HANDLE, handle);
+        }
+        self.msgs.push(msg);
+        self
+    }
+
+    pub fn flush_chain(&mut self, chain: &Chain) -> &mut Batch {
+        let mut msg = nft_msg(NFT_MSG_DELRULE, chain.family.raw(), 0);
+        msg.attr_str(NFTA_RULE_TABLE, &chain.table)
+            .attr_str(NFTA_RULE_CHAIN, &chain.name);
+        self.msgs.push(msg);
+        self
+    }
+
+    pub fn flush_table(&mut self, table: &Table) -> &mut Batch {
+        let mut msg = nft_msg(NFT_MSG_DELRULE, table.family.raw(), 0);
+        msg.attr_str(NFTA_RULE_TABLE, &table.name);
+        self.msgs.push(msg);
+        self
+    }
+
+    pub fn flush_ruleset(&mut self) -> &mut Batch {
+        self.msgs.push(nft_msg(NFT_MSG_DELTABLE, NFPROTO_UNSPEC, 0));
+        self
+    }
+}
+
+/// An nf_tables netlink connection bound to one network namespace.
+#[derive(Debug)]
+pub struct Nftables {
+    sock: Socket,
+}
+
+fn reply_family(reply: &Reply) -> errno::Result<(Family, Attrs<'_>)> {
+    if reply.payload.len() < 4 {
+        return Err(Error::Sys(Errno::from_i32(libc::EBADMSG)));
+    }
+    match Family::from_raw(reply.payload[0]) {
+        Some(f) => Ok((f, Attrs::new(&reply.payload[4..]))),
+        None => Err(Error::Sys(Errno::from_i32(libc::EAFNOSUPPORT))),
+    }
+}
+
+impl Nftables {
+    pub fn new() -> errno::Result<Nftables> {
+        Ok(Nftables {
+            sock: Socket::new(netlink::NETLINK_NETFILTER)?,
+        })
+    }
+
+    pub fn new_in(ns: &NetNS) -> errno::Result<Nftables> {
+        Ok(Nftables {
+            sock: Socket::new_in(ns, netlink::NETLINK_NETFILTER)?,
+        })
+    }
+
+    /// Applies `batch` as one transaction. The socket's send buffer is
+    /// grown to fit it; if the kernel will not allow that, e.g. without
+    /// `CAP_NET_ADMIN`, the batch is split into several transactions that
+    /// are each atomic on their own.
+    pub fn apply(&mut self, batch: &Batch) -> errno::Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let framing = batch_msg(NFNL_MSG_BATCH_BEGIN).size() + batch_msg(NFNL_MSG_BATCH_END).size();
+        let size = framing + batch.msgs.iter().map(Message::size).sum::<usize>();
+        let limit = self.sock.reserve_send_buffer(size)?;
+
+        let mut start = 0;
+        while start < batch.msgs.len() {
+            let (mut end, mut len) = (start, framing);
+            while end < batch.msgs.len() && (end == start || len + batch.msgs[end].size() <= limit)
+            {
+                len += batch.msgs[end].size();
+                end += 1;
+            }
+            let mut msgs = Vec::with_capacity(end - start + 2);
+            msgs.push(batch_msg(NFNL_MSG_BATCH_BEGIN));
+            msgs.extend(batch.msgs[start..end].iter().cloned());
+            msgs.push(batch_msg(NFNL_MSG_BATCH_END));
+            // Members carry no NLM_F_ACK, so only errors are queued and
+            // the receive buffer cannot overflow with acknowledgements.
+            self.sock.send(&mut msgs)?;
+            self.sock.drain_acks()?;
+            start = end;
+        }
+        Ok(())
+    }
+
+    pub fn add_table(&mut self, table: &Table) -> errno::Result<()> {
+        self.apply(Batch::new().add_table(table))
+    }
+
+    pub fn delete_table(&mut self, table: &Table) -> errno::Result<()> {
+        self.apply(Batch::new().delete_table(table))
+    }
+
+    pub fn add_chain(&mut self, chain: &Chain) -> errno::Result<()> {
+        self.apply(Batch::new().add_chain(chain))
+    }
+
+    pub fn delete_chain(&mut self, chain: &Chain) -> errno::Result<()> {
+        self.apply(Batch::new().delete_chain(chain))
+    }
+
+    pub fn add_rule(&mut self, rule: &Rule) -> errno::Result<()> {
+        self.apply(Batch::new().add_rule(rule))
+    }
+
+    pub fn delete_rule(&mut self, rule: &Rule) -> errno::Result<()> {
+        self.apply(Batch::new().delete_rule(rule))
+    }
+
+    pub fn flush_chain(&mut self, chain: &Chain) -> errno::Result<()> {
+        self.apply(Batch::new().flush_chain(chain))
+    }
+
+    pub fn flush_table(&mut self, table: &Table) -> errno::Result<()> {
+        self.apply(Batch::new().flush_table(table))
+    }
+
+    pub fn flush_ruleset(&mut self) -> errno::Result<()> {
+        self.apply(Batch::new().flush_ruleset())
+    }
+
+    fn dump(&mut self, ty: u16, family: Option<Family>) -> errno::Result<Vec<Reply>> {
+        let family = family.map(|f| f.raw()).unwrap_or(NFPROTO_UNSPEC);
+        self.sock.dump(nft_msg(ty, family, netlink::NLM_F_DUMP))
+    }
+
+    /// Lists tables of `family`, or of every family when `None`.
+    pub fn list_tables(&mut self, family: Option<Family>) -> errno::Result<Vec<Table>> {
+        let mut out = Vec::new();
+        for reply in self.dump(NFT_MSG_GETTABLE, family)? {
+            let (family, attrs) = reply_family(&reply)?;
+            let mut table = Table::new(family, "");
+            for (ty, data) in attrs {
+                match ty {
+                    NFTA_TABLE_NAME => table.name = netlink::parse_str(data),
+                    NFTA_TABLE_HANDLE => table.handle = netlink::parse_u64_be(data),
+                    _ => {}
+                }
+            }
+            out.push(table);
+        }
+        Ok(out)
+    }
+
+    pub fn list_chains(&mut self, family: Option<Family>) -> errno::Result<Vec<Chain>> {
+        let mut out = Vec::new();
+        for reply in self.dump(NFT_MSG_GETCHAIN, family)? {
+            let (family, attrs) = reply_family(&reply)?;
+            let mut chain = Chain::new(family, "", "");
+            let mut hook = None;
+            let mut priority = 0;
+            let mut ty = ChainType::Filter;
+            let mut policy = Policy::Accept;
+            for (t, data) in attrs {
+                match t {
+                    NFTA_CHAIN_TABLE => chain.table = netlink::parse_str(data),
+                    NFTA_CHAIN_NAME => chain.name = netlink::parse_str(data),
+                    NFTA_CHAIN_HANDLE => chain.handle = netlink::parse_u64_be(data),
+                    NFTA_CHAIN_TYPE => {
+                        ty = ChainType::from_name(&netlink::parse_str(data)).unwrap_or(ty)
+                    }
+                    NFTA_CHAIN_POLICY if netlink::parse_u32_be(data) == Some(NF_DROP) => {
+                        policy = Policy::Drop
+                    }
+                    NFTA_CHAIN_HOOK => {
+                        for (h, hdata) in Attrs::new(data) {
+                            match h {
+                                NFTA_HOOK_HOOKNUM => {
+                                    hook = netlink::parse_u32_be(hdata).and_then(Hook::from_raw)
+                                }
+                                NFTA_HOOK_PRIORITY => {
+                                    priority = netlink::parse_u32_be(hdata).unwrap_or(0) as i32
+                                }
+                                _ => {}
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            if let Some(hook) = hook {
+                chain.base = Some(BaseChain {
+                    ty,
+                    hook,
+                    priority,
+                    policy,
+                });
+            }
+            out.push(chain);
+        }
+        Ok(out)
+    }
+
+    /// Lists the rules of `chain` in `table`. Only the family, table,
+    /// chain and handle are decoded, so `exprs` is always empty and a
+    /// listed rule does not compare equal to the `Rule` it was added
+    /// from. The results are meant to be passed to `delete_rule`.
+    pub fn list_rules(
+        &mut self,
+        family: Family,
+        table: &str,
+        chain: &str,
+    ) -> errno::Result<Vec<Rule>> {
+        let mut out = Vec::new();
+        for reply in self.dump(NFT_MSG_GETRULE, Some(family))? {
+            let (family, attrs) = reply_family(&reply)?;
+            let mut rule = Rule::new(family, "", "");
+            for (ty, data) in attrs {
+                match ty {
+                    NFTA_RULE_TABLE => rule.table = netlink::parse_str(data),
+                    NFTA_RULE_CHAIN => rule.chain = netlink::parse_str(data),
+                    NFTA_RULE_HANDLE => rule.handle = netlink::parse_u64_be(data),
+                    _ => {}
+                }
+            }
+            if rule.table == table && rule.chain == chain {
+                out.push(rule);
+            }
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn be(v: u32) -> Vec<u8> {
+        v.to_be_bytes().to_vec()
+    }
+
+    // A nested NFTA_DATA_VALUE attribute as the kernel expects it.
+    fn value(v: &[u8]) -> Vec<u8> {
+        let mut b = ((4 + v.len()) as u16).to_ne_bytes().to_vec();
+        b.extend_from_slice(&NFTA_DATA_VALUE.to_ne_bytes());
+        b.extend_from_slice(v);
+        b.resize(netlink::align(b.len()), 0);
+        b
+    }
+
+    // An expression's name and its attributes.
+    type Encoded = (String, Vec<(u16, Vec<u8>)>);
+
+    // The name and attributes of each expression in the encoded rule.
+    fn exprs(rule: &Rule) -> Vec<Encoded> {
+        let mut batch = Batch::new();
+        batch.add_rule(rule);
+        let buf = batch.msgs[0].finish(1).to_vec();
+        let reply = &netlink::messages(&buf)[0];
+        let list = Attrs::new(&reply.payload[4..])
+            .find(|(ty, _)| *ty == NFTA_RULE_EXPRESSIONS)
+            .unwrap()
+            .1;
+        Attrs::new(list)
+            .map(|(_, elem)| {
+                let mut name = String::new();
+                let mut data = Vec::new();
+                for (ty, v) in Attrs::new(elem) {
+                    match ty {
+                        NFTA_EXPR_NAME => name = netlink::parse_str(v),
+                        NFTA_EXPR_DATA => {
+                            data = Attrs::new(v).map(|(t, d)| (t, d.to_vec())).collect()
+                        }
+                        _ => {}
+                    }
+                }
+                (name, data)
+            })
+            .collect()
+    }
+
+    fn expr(name: &str, attrs: Vec<(u16, Vec<u8>)>) -> Encoded {
+        (name.to_string(), attrs)
+    }
+
+    #[test]
+    fn masquerade_subnet() {
+        let rule = Rule::new(Family::Ip, "nat", "post")
+            .saddr(IpAddr::V4(Ipv4Addr::new(10, 1, 2, 3)), 30)
+            .masquerade();
+        assert_eq!(
+            exprs(&rule),
+            vec![
+                expr(
+                    "payload",
+                    vec![(1, be(1)), (2, be(1)), (3, be(12)), (4, be(4))]
+                ),
+                expr(
+                    "bitwise",
+                    vec![
+                        (1, be(1)),
+                        (2, be(1)),
+                        (3, be(4)),
+                        (4, value(&[0xff, 0xff, 0xff, 0xfc])),
+                        (5, value(&[0, 0, 0, 0])),
+                    ]
+                ),
+                expr(
+                    "cmp",
+                    vec![(1, be(1)), (2, be(0)), (3, value(&[10, 1, 2, 0]))]
+                ),
+                expr("masq", vec![]),
+            ]
+        );
+
+        // A full length prefix needs no mask.
+        let rule =
+            Rule::new(Family::Ip, "nat", "post").saddr(IpAddr::V4(Ipv4Addr::new(10, 1, 2, 3)), 32);
+        let names: Vec<String> = exprs(&rule).into_iter().map(|e| e.0).collect();
+        assert_eq!(names, vec!["payload", "cmp"]);
+    }
+
+    #[test]
+    fn dnat_port() {
+        let rule = Rule::new(Family::Ip, "nat", "pre")
+            .dport(Protocol::Tcp, 8080)
+            .dnat(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)), Some(80));
+        assert_eq!(
+            exprs(&rule),
+            vec![
+                expr("meta", vec![(2, be(16)), (1, be(1))]),
+                expr("cmp", vec![(1, be(1)), (2, be(0)), (3, value(&[6]))]),
+                expr(
+                    "payload",
+                    vec![(1, be(1)), (2, be(2)), (3, be(2)), (4, be(2))]
+                ),
+                expr(
+                    "cmp",
+                    vec![(1, be(1)), (2, be(0)), (3, value(&[0x1f, 0x90]))]
+                ),
+                expr("immediate", vec![(1, be(1)), (2, value(&[10, 0, 0, 2]))]),
+                expr("immediate", vec![(1, be(2)), (2, value(&[0, 80]))]),
+                expr("nat", vec![(1, be(1)), (2, be(2)), (3, be(1)), (5, be(2))]),
+            ]
+        );
+    }
+
+    #[test]
+    fn large_batch() {
+        // Applying rules needs root.
+        if unsafe { libc::geteuid() } != 0 {
+            return;
+        }
+        let ns = NetNS::builder().build_detached().unwrap();
+        let mut nft = Nftables::new_in(&ns).unwrap();
+        for count in [300u16, 1000, 5000] {
+            let table = Table::new(Family::Ip, "big");
+            let chain =
+                Chain::new(Family::Ip, "big", "input").hook(ChainType::Filter, Hook::Input, 0);
+            let mut batch = Batch::new();
+            batch.add_table(&table).add_chain(&chain);
+            for port in 0..count {
+                batch.add_rule(
+                    &Rule::new(Family::Ip, "big", "input")
+                        .dport(Protocol::Tcp, port)
+                        .accept(),
+                );
+            }
+            nft.apply(&batch).unwrap();
+            let rules = nft.list_rules(Family::Ip, "big", "input").unwrap();
+            assert_eq!(rules.len(), count as usize);
+            nft.delete_table(&table).unwrap();
+        }
+    }
+
+    #[test]
+    fn prefix_masks() {
+        assert_eq!(prefix_mask(4, 0), vec![0; 4]);
+        assert_eq!(prefix_mask(4, 32), vec![0xff; 4]);
+        assert_eq!(prefix_mask(4, 20), vec![0xff, 0xff, 0xf0, 0]);
+        assert_eq!(prefix_mask(16, 0), vec![0; 16]);
+        assert_eq!(prefix_mask(16, 128), vec![0xff; 16]);
+        let mut mask = vec![0xff; 8];
+        mask.push(0x80);
+        mask.resize(16, 0);
+        assert_eq!(prefix_mask(16, 65), mask);
+    }
+}
@@ -0,0 +1,232 @@
+use std::fs::File;
+use std::mem;
+use std::os::unix::io::{AsRawFd, OwnedFd};
+use std::process::{Child, Command};
+use std::ptr;
+use std::thread;
+
+use super::errno;
+use super::namespace::{Namespace, NsType};
+use super::netns_linux::{
+    clone3, pidfd_open, pidfd_send_signal, pipe, setns, waitpid, NetNS, CLONE_NEWNET, CLONE_NEWPID,
+};
+
+/// A PID namespace kept alive by a minimal init process running as its
+/// PID 1. Init reaps orphaned processes and forwards the signals it
+/// receives to every other process in the namespace.
+///
+/// Dropping the handle kills init, which makes the kernel kill everything
+/// else in the namespace. Init also exits if the creating process dies.
+#[derive(Debug)]
+pub struct PidNS {
+    init: i32,
+    pidfd: OwnedFd,
+    ns: Namespace,
+    netns: Option<NetNS>,
+    // Init exits once the write end of its pipe is closed.
+    _alive: File,
+    reaped: bool,
+}
+
+impl Drop for PidNS {
+    fn drop(&mut self) {
+        if self.reaped {
+            return;
+        }
+        let _ = self.signal(libc::SIGKILL);
+        // Init only finishes exiting once the processes started by `spawn`
+        // have been reaped by their parent, which may happen much later.
+        let init = self.init;
+        thread::spawn(move || waitpid(init));
+    }
+}
+
+impl PidNS {
+    /// Creates a PID namespace; its processes share the caller's network
+    /// namespace.
+    pub fn new() -> errno::Result<PidNS> {
+        PidNS::start(0, None)
+    }
+
+    /// Creates a PID namespace together with a new network namespace that
+    /// all of its processes are placed in. `lo` is left down.
+    pub fn with_netns() -> errno::Result<PidNS> {
+        PidNS::start(CLONE_NEWNET, None)
+    }
+
+    /// Creates a PID namespace whose processes run in `netns`.
+    pub fn new_in(netns: &NetNS) -> errno::Result<PidNS> {
+        PidNS::start(0, Some(netns))
+    }
+
+    fn start(flags: i32, netns: Option<&NetNS>) -> errno::Result<PidNS> {
+        let netns_fd = netns.map(|ns| ns.fd());
+        let (alive_r, alive_w) = pipe()?;
+        // The child only runs `setns` and `init`, which never return.
+        let pid = unsafe { clone3(CLONE_NEWPID | flags, libc::SIGCHLD)? };
+        if pid == 0 {
+            if let Some(fd) = netns_fd {
+                if setns(fd, CLONE_NEWNET).is_err() {
+                    unsafe { libc::_exit(1) };
+                }
+            }
+            init(alive_r.as_raw_fd());
+        }
+        drop(alive_r);
+
+        let opened =
+            pidfd_open(pid).and_then(|fd| Ok((fd, Namespace::get_from_process(pid, NsType::Pid)?)));
+        let (pidfd, pid_ns) = match opened {
+            Ok(v) => v,
+            Err(e) => {
+                unsafe { libc::kill(pid, libc::SIGKILL) };
+                let _ = waitpid(pid);
+                return Err(e);
+            }
+        };
+        // From here on dropping `ns` cleans up after a failure.
+        let mut ns = PidNS {
+            init: pid,
+            pidfd,
+            ns: pid_ns,
+            netns: None,
+            _alive: alive_w,
+            reaped: false,
+        };
+        // Init may not have joined `netns` yet, so only a namespace clone3
+        // created can be read back from it.
+        ns.netns = match netns {
+            Some(netns) => Some(netns.try_clone()?),
+            None if flags & CLONE_NEWNET != 0 => Some(NetNS::get_from_process(pid)?),
+            None => None,
+        };
+        Ok(ns)
+    }
+
+    /// The pid of init, as seen from the caller's namespace.
+    pub fn init_pid(&self) -> i32 {
+        self.init
+    }
+
+    pub fn pidfd(&self) -> i32 {
+        self.pidfd.as_raw_fd()
+    }
+
+    pub fn namespace(&self) -> &Namespace {
+        &self.ns
+    }
+
+    /// The network namespace processes in this namespace run in, if it is
+    /// not the caller's.
+    pub fn netns(&self) -> Option<&NetNS> {
+        self.netns.as_ref()
+    }
+
+    /// Starts `cmd` inside the namespace. The caller stays its parent, so
+    /// the returned `Child` works as usual; its descendants are reaped by
+    /// init if they outlive it. Note that `/proc` still shows the caller's
+    /// view unless the command also gets a mount namespace with `/proc`
+    /// remounted.
+    pub fn spawn(&self, cmd: &mut Command) -> errno::Result<Child> {
+        // setns on a PID namespace only changes where children of the
+        // calling thread are created, so fork from a thread that joined it.
+        self.ns.run(|| {
+            if let Some(ref netns) = self.netns {
+                NetNS::set(netns)?;
+            }
+            Ok(cmd.spawn()?)
+        })
+    }
+
+    /// Sends `signal` to init, which passes it on to all other processes
+    /// in the namespace. `SIGKILL` kills init itself and thereby the whole
+    /// namespace.
+    pub fn signal(&self, signal: i32) -> errno::Result<()> {
+        pidfd_send_signal(self.pidfd.as_raw_fd(), signal)
+    }
+
+    /// Kills every process in the namespace and waits for init to exit.
+    /// Processes started with `spawn` must have been waited for, or this
+    /// blocks until they are.
+    pub fn shutdown(mut self) -> errno::Result<()> {
+        self.signal(libc::SIGKILL)?;
+        waitpid(self.init)?;
+        self.reaped = true;
+        Ok(())
+    }
+}
+
+// PID 1 of the namespace. Runs in a child of a possibly multithreaded
+// process, so it sticks to plain system calls. `alive` is the read end of
+// a pipe that reports EOF once the handle or its process is gone.
+fn init(alive: i32) -> ! {
+    unsafe {
+        // Don't keep the creator's sockets and pipes open.
+        if alive > 0 {
+            libc::syscall(libc::SYS_close_range, 0, alive - 1, 0);
+        }
+        libc::syscall(libc::SYS_close_range, alive + 1, u32::MAX, 0);
+
+        let mut set: libc::sigset_t = mem::zeroed();
+        libc::sigfillset(&mut set);
+        libc::sigprocmask(libc::SIG_SETMASK, &set, ptr::null_mut());
+        let sfd = libc::signalfd(-1, &set, libc::SFD_CLOEXEC);
+        if sfd < 0 {
+            libc::_exit(1);
+        }
+
+        let mut info: libc::signalfd_siginfo = mem::zeroed();
+        loop {
+            let mut fds = [
+                libc::pollfd {
+                    fd: sfd,
+                    events: libc::POLLIN,
+                    revents: 0,
+                },
+                libc::pollfd {
+                    fd: alive,
+                    events: libc::POLLIN,
+                    revents: 0,
+                },
+            ];
+            if libc::poll(fds.as_mut_ptr(), 2, -1) < 0 {
+                continue;
+            }
+            if fds[1].revents != 0 {
+                libc::_exit(0);
+            }
+            let n = libc::read(
+                sfd,
+                &mut info as *mut libc::signalfd_siginfo as *mut libc::c_void,
+                mem::size_of::<libc::signalfd_siginfo>(),
+            );
+            if n <= 0 {
+                continue;
+            }
+            match info.ssi_signo as i32 {
+                libc::SIGCHLD => while libc::waitpid(-1, ptr::null_mut(), libc::WNOHANG) > 0 {},
+                sig => {
+                    libc::kill(-1, sig);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_in_keeps_netns() {
+        // Creating namespaces needs root.
+        if unsafe { libc::geteuid() } != 0 {
+            return;
+        }
+        let netns = NetNS::builder().build_detached().unwrap();
+        for _ in 0..20 {
+            let pidns = PidNS::new_in(&netns).unwrap();
+            assert!(pidns.netns().unwrap().same_as(&netns).unwrap());
+        }
+    }
+}
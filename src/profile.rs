@@ -0,0 +1,370 @@
+use std::fs;
+use std::path::Path;
+
+use super::errno::{self, Error};
+use super::netns_linux::NetNS;
+use super::sysctl::{self, SysctlValue};
+
+/// A set of network sysctls applied together. The named fields cover the
+/// settings most namespaces need; `extra` takes any other `net.*` key.
+///
+/// Profiles can be loaded from sysctl.conf(5) files with `from_file`:
+///
+/// ```text
+/// net.ipv4.ip_forward = 1
+/// net.ipv4.conf.all.rp_filter = 2
+/// net.ipv4.ping_group_range = 0 2147483647
+/// ```
+///
+/// With the `toml` feature, `from_toml_file` reads the named fields
+/// directly and other sysctls from an `[extra]` table:
+///
+/// ```toml
+/// ip_forward = true
+/// rp_filter = 2
+/// disable_ipv6 = false
+/// accept_ra = 0
+/// accept_dad = 0
+/// ping_group_range = [0, 2147483647]
+///
+/// [extra]
+/// "net.ipv4.tcp_syncookies" = 1
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SysctlProfile {
+    pub ip_forward: Option<bool>,
+    pub rp_filter: Option<i64>,
+    pub disable_ipv6: Option<bool>,
+    pub accept_ra: Option<i64>,
+    pub accept_dad: Option<i64>,
+    pub ping_group_range: Option<(i64, i64)>,
+    pub extra: Vec<(String, SysctlValue)>,
+}
+
+impl SysctlProfile {
+    /// The sysctls set by this profile, in the order they are applied.
+    /// Per-interface settings are written to both `all` and `default`.
+    pub fn entries(&self) -> Vec<(String, SysctlValue)> {
+        let mut out = Vec::new();
+        if let Some(v) = self.ip_forward {
+            out.push(("net.ipv4.ip_forward".to_string(), v.into()));
+        }
+        let conf: [(&str, &str, Option<i64>); 4] = [
+            ("ipv4", "rp_filter", self.rp_filter),
+            ("ipv6", "disable_ipv6", self.disable_ipv6.map(|v| v as i64)),
+            ("ipv6", "accept_ra", self.accept_ra),
+            ("ipv6", "accept_dad", self.accept_dad),
+        ];
+        for (family, key, value) in conf.iter() {
+            if let Some(v) = value {
+                for dev in ["all", "default"].iter() {
+                    out.push((
+                        format!("net.{}.conf.{}.{}", family, dev, key),
+                        SysctlValue::Int(*v),
+                    ));
+                }
+            }
+        }
+        if let Some((lo, hi)) = self.ping_group_range {
+            out.push((
+                "net.ipv4.ping_group_range".to_string(),
+                SysctlValue::Ints(vec![lo, hi]),
+            ));
+        }
+        out.extend(self.extra.iter().cloned());
+        out
+    }
+
+    /// Applies the profile in the calling thread's network namespace. If
+    /// any write fails, the values changed so far are restored and the
+    /// error is returned.
+    pub fn apply(&self) -> errno::Result<()> {
+        let mut applied: Vec<(String, SysctlValue)> = Vec::new();
+        for (name, value) in self.entries() {
+            let res = sysctl::read(&name).and_then(|old| {
+                sysctl::write(&name, &value)?;
+                Ok(old)
+            });
+            match res {
+                Ok(old) => applied.push((name, old)),
+                Err(e) => {
+                    for (name, old) in applied.iter().rev() {
+                        let _ = sysctl::write(name, old);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads a profile in sysctl.conf(5) syntax, see `parse`.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> errno::Result<SysctlProfile> {
+        SysctlProfile::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Parses sysctl.conf(5) syntax: one `name = value` per line with the
+    /// full `net.*` name, and whole-line comments starting with `#` or
+    /// `;`. `net.ipv4.ip_forward` and `net.ipv4.ping_group_range` set the
+    /// matching fields; every other name goes to `extra`, in file order.
+    /// Other lines, names outside `net.*` and the `-name` form for
+    /// ignoring failures fail with `Error::Parse` naming the line.
+    pub fn parse(s: &str) -> errno::Result<SysctlProfile> {
+        let mut profile = SysctlProfile::default();
+        for (n, line) in s.lines().enumerate() {
+            let invalid = || Error::Parse { line: n + 1 };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            let eq = line.find('=').ok_or_else(invalid)?;
+            let (name, value) = (line[..eq].trim(), line[eq + 1..].trim());
+            let net = name.starts_with("net.") || name.starts_with("net/");
+            if !net || name.contains(char::is_whitespace) || value.is_empty() {
+                return Err(invalid());
+            }
+            let value = SysctlValue::parse(value);
+            match name {
+                "net.ipv4.ip_forward" => {
+                    profile.ip_forward = Some(as_bool(&value).ok_or_else(invalid)?)
+                }
+                "net.ipv4.ping_group_range" => {
+                    profile.ping_group_range = Some(as_pair(&value).ok_or_else(invalid)?)
+                }
+                _ => profile.extra.push((name.to_string(), value)),
+            }
+        }
+        Ok(profile)
+    }
+
+    /// Reads a profile in the TOML form, see `from_toml_str`.
+    #[cfg(feature = "toml")]
+    pub fn from_toml_file<P: AsRef<Path>>(path: P) -> errno::Result<SysctlProfile> {
+        SysctlProfile::from_toml_str(&fs::read_to_string(path)?)
+    }
+
+    /// Parses the TOML form shown above. Keys in `[extra]` may be quoted
+    /// or dotted. Syntax errors fail with `Error::Parse`; unknown fields
+    /// and values of the wrong type with `EINVAL`.
+    #[cfg(feature = "toml")]
+    pub fn from_toml_str(s: &str) -> errno::Result<SysctlProfile> {
+        let table: toml::Table = s.parse().map_err(|e: toml::de::Error| {
+            let start = e.span().map_or(0, |r| r.start);
+            Error::Parse {
+                line: s[..start].matches('\n').count() + 1,
+            }
+        })?;
+        let mut profile = SysctlProfile::default();
+        for (key, value) in table.iter() {
+            let int = || value.as_integer().ok_or_else(invalid);
+            let flag = || value.as_bool().ok_or_else(invalid);
+            match key.as_str() {
+                "ip_forward" => profile.ip_forward = Some(flag()?),
+                "rp_filter" => profile.rp_filter = Some(int()?),
+                "disable_ipv6" => profile.disable_ipv6 = Some(flag()?),
+                "accept_ra" => profile.accept_ra = Some(int()?),
+                "accept_dad" => profile.accept_dad = Some(int()?),
+                "ping_group_range" => {
+                    let value = toml_value(value).ok_or_else(invalid)?;
+                    profile.ping_group_range = Some(as_pair(&value).ok_or_else(invalid)?);
+                }
+                "extra" => {
+                    let extra = value.as_table().ok_or_else(invalid)?;
+                    flatten_extra("", extra, &mut profile.extra)?;
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(profile)
+    }
+}
+
+fn as_bool(v: &SysctlValue) -> Option<bool> {
+    match v {
+        SysctlValue::Int(0) => Some(false),
+        SysctlValue::Int(1) => Some(true),
+        _ => None,
+    }
+}
+
+fn as_pair(v: &SysctlValue) -> Option<(i64, i64)> {
+    match v {
+        SysctlValue::Ints(v) if v.len() == 2 => Some((v[0], v[1])),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "toml")]
+fn invalid() -> Error {
+    Error::Sys(super::errno::Errno::from_i32(libc::EINVAL))
+}
+
+#[cfg(feature = "toml")]
+fn toml_value(v: &toml::Value) -> Option<SysctlValue> {
+    match v {
+        toml::Value::Integer(i) => Some(SysctlValue::Int(*i)),
+        toml::Value::Boolean(b) => Some(SysctlValue::Int(*b as i64)),
+        toml::Value::String(s) => Some(SysctlValue::Str(s.clone())),
+        toml::Value::Array(a) => a
+            .iter()
+            .map(|v| v.as_integer())
+            .collect::<Option<Vec<i64>>>()
+            .map(SysctlValue::Ints),
+        _ => None,
+    }
+}
+
+// Dotted keys such as `net.core.somaxconn = 1` arrive as nested tables.
+#[cfg(feature = "toml")]
+fn flatten_extra(
+    prefix: &str,
+    table: &toml::Table,
+    out: &mut Vec<(String, SysctlValue)>,
+) -> errno::Result<()> {
+    for (key, value) in table.iter() {
+        let name = match prefix {
+            "" => key.clone(),
+            _ => format!("{}.{}", prefix, key),
+        };
+        match value {
+            toml::Value::Table(t) => flatten_extra(&name, t, out)?,
+            v => out.push((name, toml_value(v).ok_or_else(invalid)?)),
+        }
+    }
+    Ok(())
+}
+
+impl NetNS {
+    /// Creates a new network namespace like `NetNS::new` and applies
+    /// `profile` in it. On failure the calling thread is moved back to
+    /// its previous namespace and the new one is discarded.
+    pub fn new_with_sysctl(profile: &SysctlProfile) -> errno::Result<NetNS> {
+        NetNS::builder()
+            .loopback(false)
+            .sysctl(profile.clone())
+            .build()
+    }
+
+    pub fn new_with_sysctl_file<P: AsRef<Path>>(path: P) -> errno::Result<NetNS> {
+        NetNS::new_with_sysctl(&SysctlProfile::from_file(path)?)
+    }
+
+    /// Applies `profile` inside this namespace, see `SysctlProfile::apply`.
+    pub fn apply_sysctl(&self, profile: &SysctlProfile) -> errno::Result<()> {
+        self.run(|| profile.apply())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let profile = SysctlProfile::parse(
+            "
+            # defaults for tenant namespaces
+            net.ipv4.ip_forward = 1
+            ; strict reverse path filtering
+            net.ipv4.conf.all.rp_filter = 2
+            net.ipv4.ping_group_range = 0\t2147483647
+            net/ipv4/conf/eth0.100/forwarding=0
+            net.ipv4.tcp_congestion_control = reno
+            ",
+        )
+        .unwrap();
+        assert_eq!(profile.ip_forward, Some(true));
+        assert_eq!(profile.rp_filter, None);
+        assert_eq!(profile.ping_group_range, Some((0, 2147483647)));
+        assert_eq!(
+            profile.extra,
+            vec![
+                (
+                    "net.ipv4.conf.all.rp_filter".to_string(),
+                    SysctlValue::Int(2)
+                ),
+                (
+                    "net/ipv4/conf/eth0.100/forwarding".to_string(),
+                    SysctlValue::Int(0)
+                ),
+                (
+                    "net.ipv4.tcp_congestion_control".to_string(),
+                    SysctlValue::Str("reno".to_string())
+                ),
+            ]
+        );
+
+        for bad in [
+            "net.ipv4.ip_forward = 2",
+            "kernel.pid_max = 4096",
+            "-net.ipv4.ip_forward = 1",
+            "net.ipv4.ip_forward",
+            "net.ipv4.ip_forward =",
+            "[extra]",
+        ] {
+            assert!(SysctlProfile::parse(bad).is_err(), "{}", bad);
+        }
+        assert_eq!(
+            SysctlProfile::parse("net.ipv4.ip_forward = 1\n\nkernel.hostname = x"),
+            Err(Error::Parse { line: 3 })
+        );
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn parse_toml() {
+        let profile = SysctlProfile::from_toml_str(
+            r#"
+            # defaults for tenant namespaces
+            ip_forward = true
+            rp_filter = 2
+            disable_ipv6 = false
+            ping_group_range = [0, 2_147_483_647]
+
+            [extra]
+            "net.ipv4.tcp_congestion_control" = "reno"
+            net.core.somaxconn = 1024
+            "#,
+        )
+        .unwrap();
+        assert_eq!(profile.ip_forward, Some(true));
+        assert_eq!(profile.rp_filter, Some(2));
+        assert_eq!(profile.disable_ipv6, Some(false));
+        assert_eq!(profile.ping_group_range, Some((0, 2147483647)));
+        assert_eq!(
+            profile.extra,
+            vec![
+                ("net.core.somaxconn".to_string(), SysctlValue::Int(1024)),
+                (
+                    "net.ipv4.tcp_congestion_control".to_string(),
+                    SysctlValue::Str("reno".to_string())
+                ),
+            ]
+        );
+
+        assert!(SysctlProfile::from_toml_str("ip_forward = 2").is_err());
+        assert!(SysctlProfile::from_toml_str("unknown = 1").is_err());
+        assert_eq!(
+            SysctlProfile::from_toml_str("rp_filter = 1\nip_forward = = 1"),
+            Err(Error::Parse { line: 2 })
+        );
+    }
+
+    #[test]
+    fn entries() {
+        let profile = SysctlProfile {
+            ip_forward: Some(true),
+            accept_ra: Some(0),
+            ..Default::default()
+        };
+        let names: Vec<String> = profile.entries().into_iter().map(|(n, _)| n).collect();
+        assert_eq!(
+            names,
+            vec![
+                "net.ipv4.ip_forward",
+                "net.ipv6.conf.all.accept_ra",
+                "net.ipv6.conf.default.accept_ra",
+            ]
+        );
+    }
+}
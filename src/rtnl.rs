@@ -0,0 +1,334 @@
+use std::net::IpAddr;
+
+use super::errno::{self, Errno, Error};
+use super::netlink::{self, Attrs, Message, Socket};
+use super::netns_linux::NetNS;
+
+pub const RTM_NEWLINK: u16 = 16;
+pub const RTM_DELLINK: u16 = 17;
+pub const RTM_GETLINK: u16 = 18;
+pub const RTM_NEWADDR: u16 = 20;
+pub const RTM_DELADDR: u16 = 21;
+pub const RTM_GETADDR: u16 = 22;
+pub const RTM_NEWROUTE: u16 = 24;
+pub const RTM_DELROUTE: u16 = 25;
+pub const RTM_GETROUTE: u16 = 26;
+pub const RTM_NEWNSID: u16 = 88;
+pub const RTM_DELNSID: u16 = 89;
+pub const RTM_GETNSID: u16 = 90;
+
+pub const IFLA_ADDRESS: u16 = 1;
+pub const IFLA_IFNAME: u16 = 3;
+pub const IFLA_MTU: u16 = 4;
+pub const IFLA_LINKINFO: u16 = 18;
+pub const IFLA_NET_NS_FD: u16 = 28;
+
+const IFLA_INFO_KIND: u16 = 1;
+const IFLA_INFO_DATA: u16 = 2;
+const VETH_INFO_PEER: u16 = 1;
+
+pub const IFA_ADDRESS: u16 = 1;
+pub const IFA_LOCAL: u16 = 2;
+
+pub const NETNSA_NSID: u16 = 1;
+pub const NETNSA_FD: u16 = 3;
+
+// What NETNSA_NSID holds for peers without an ID.
+const NETNSA_NSID_NOT_ASSIGNED: i32 = -1;
+
+pub const RTA_DST: u16 = 1;
+pub const RTA_OIF: u16 = 4;
+pub const RTA_GATEWAY: u16 = 5;
+
+const RT_TABLE_MAIN: u8 = 254;
+const RTPROT_BOOT: u8 = 3;
+const RT_SCOPE_UNIVERSE: u8 = 0;
+const RT_SCOPE_LINK: u8 = 253;
+const RTN_UNICAST: u8 = 1;
+
+pub const IFF_UP: u32 = 0x1;
+
+fn family_of(addr: IpAddr) -> u8 {
+    match addr {
+        IpAddr::V4(_) => libc::AF_INET as u8,
+        IpAddr::V6(_) => libc::AF_INET6 as u8,
+    }
+}
+
+/// Encodes a `struct ifinfomsg`.
+pub fn ifinfomsg(family: u8, index: i32, flags: u32, change: u32) -> [u8; 16] {
+    let mut b = [0u8; 16];
+    b[0] = family;
+    b[4..8].copy_from_slice(&index.to_ne_bytes());
+    b[8..12].copy_from_slice(&flags.to_ne_bytes());
+    b[12..16].copy_from_slice(&change.to_ne_bytes());
+    b
+}
+
+/// Encodes a `struct ifaddrmsg`.
+fn ifaddrmsg(family: u8, prefix: u8, scope: u8, index: u32) -> [u8; 8] {
+    let mut b = [0u8; 8];
+    b[0] = family;
+    b[1] = prefix;
+    b[3] = scope;
+    b[4..8].copy_from_slice(&index.to_ne_bytes());
+    b
+}
+
+/// Encodes a `struct rtmsg`.
+fn rtmsg(family: u8, dst_len: u8, scope: u8) -> [u8; 12] {
+    let mut b = [0u8; 12];
+    b[0] = family;
+    b[1] = dst_len;
+    b[4] = RT_TABLE_MAIN;
+    b[5] = RTPROT_BOOT;
+    b[6] = scope;
+    b[7] = RTN_UNICAST;
+    b
+}
+
+/// Encodes a `struct rtgenmsg`, padded to the netlink alignment.
+fn rtgenmsg(family: u8) -> [u8; 4] {
+    [family, 0, 0, 0]
+}
+
+// The NSID carried by an RTM_*NSID payload, or `None` if the peer has
+// none, which the kernel reports as -1. Shared with `monitor`.
+pub(crate) fn parse_nsid(payload: &[u8]) -> Option<i32> {
+    Attrs::new(payload.get(4..)?)
+        .find(|&(ty, _)| ty == NETNSA_NSID)
+        .and_then(|(_, data)| netlink::parse_i32(data))
+        .filter(|&id| id != NETNSA_NSID_NOT_ASSIGNED)
+}
+
+/// A rtnetlink connection for link, address and route configuration in
+/// one network namespace.
+#[derive(Debug)]
+pub struct Rtnl {
+    sock: Socket,
+}
+
+impl Rtnl {
+    pub fn new() -> errno::Result<Rtnl> {
+        Ok(Rtnl {
+            sock: Socket::new(netlink::NETLINK_ROUTE)?,
+        })
+    }
+
+    pub fn new_in(ns: &NetNS) -> errno::Result<Rtnl> {
+        Ok(Rtnl {
+            sock: Socket::new_in(ns, netlink::NETLINK_ROUTE)?,
+        })
+    }
+
+    pub fn link_index(&mut self, name: &str) -> errno::Result<u32> {
+        let mut msg = Message::new(RTM_GETLINK, 0);
+        msg.push(&ifinfomsg(libc::AF_UNSPEC as u8, 0, 0, 0))
+            .attr_str(IFLA_IFNAME, name);
+        let reply = self.sock.query(msg)?;
+        if reply.payload.len() < 16 {
+            return Err(Error::Sys(Errno::from_i32(libc::EBADMSG)));
+        }
+        Ok(netlink::parse_u32(&reply.payload[4..8]).unwrap_or(0))
+    }
+
+    pub fn link_name(&mut self, index: u32) -> errno::Result<String> {
+        let mut msg = Message::new(RTM_GETLINK, 0);
+        msg.push(&ifinfomsg(libc::AF_UNSPEC as u8, index as i32, 0, 0));
+        let reply = self.sock.query(msg)?;
+        if reply.payload.len() < 16 {
+            return Err(Error::Sys(Errno::from_i32(libc::EBADMSG)));
+        }
+        for (ty, data) in Attrs::new(&reply.payload[16..]) {
+            if ty == IFLA_IFNAME {
+                return Ok(netlink::parse_str(data));
+            }
+        }
+        Err(Error::Sys(Errno::from_i32(libc::ENODEV)))
+    }
+
+    fn link_set_flags(&mut self, index: u32, flags: u32) -> errno::Result<()> {
+        let mut msg = Message::new(RTM_NEWLINK, 0);
+        msg.push(&ifinfomsg(
+            libc::AF_UNSPEC as u8,
+            index as i32,
+            flags,
+            IFF_UP,
+        ));
+        self.sock.request(msg)
+    }
+
+    pub fn link_set_up(&mut self, index: u32) -> errno::Result<()> {
+        self.link_set_flags(index, IFF_UP)
+    }
+
+    pub fn link_set_down(&mut self, index: u32) -> errno::Result<()> {
+        self.link_set_flags(index, 0)
+    }
+
+    /// Moves a link into another network namespace.
+    pub fn link_set_ns(&mut self, index: u32, ns: &NetNS) -> errno::Result<()> {
+        let mut msg = Message::new(RTM_NEWLINK, 0);
+        msg.push(&ifinfomsg(libc::AF_UNSPEC as u8, index as i32, 0, 0))
+            .attr_u32(IFLA_NET_NS_FD, ns.fd() as u32);
+        self.sock.request(msg)
+    }
+
+    /// Creates a veth pair. The `peer` end is created directly in `peer_ns`
+    /// when given, otherwise next to `name`.
+    pub fn link_add_veth(
+        &mut self,
+        name: &str,
+        peer: &str,
+        peer_ns: Option<&NetNS>,
+    ) -> errno::Result<()> {
+        let mut msg = Message::new(RTM_NEWLINK, netlink::NLM_F_CREATE | netlink::NLM_F_EXCL);
+        msg.push(&ifinfomsg(libc::AF_UNSPEC as u8, 0, 0, 0))
+            .attr_str(IFLA_IFNAME, name)
+            .nest_start(IFLA_LINKINFO)
+            .attr_str(IFLA_INFO_KIND, "veth")
+            .nest_start(IFLA_INFO_DATA)
+            .nest_start(VETH_INFO_PEER)
+            .push(&ifinfomsg(libc::AF_UNSPEC as u8, 0, 0, 0))
+            .attr_str(IFLA_IFNAME, peer);
+        if let Some(ns) = peer_ns {
+            msg.attr_u32(IFLA_NET_NS_FD, ns.fd() as u32);
+        }
+        msg.nest_end().nest_end().nest_end();
+        self.sock.request(msg)
+    }
+
+    pub fn link_del(&mut self, index: u32) -> errno::Result<()> {
+        let mut msg = Message::new(RTM_DELLINK, 0);
+        msg.push(&ifinfomsg(libc::AF_UNSPEC as u8, index as i32, 0, 0));
+        self.sock.request(msg)
+    }
+
+    pub fn addr_add(&mut self, index: u32, addr: IpAddr, prefix: u8) -> errno::Result<()> {
+        let mut msg = Message::new(RTM_NEWADDR, netlink::NLM_F_CREATE | netlink::NLM_F_EXCL);
+        let data = netlink::addr_bytes(addr);
+        msg.push(&ifaddrmsg(
+            family_of(addr),
+            prefix,
+            RT_SCOPE_UNIVERSE,
+            index,
+        ))
+        .attr(IFA_LOCAL, &data)
+        .attr(IFA_ADDRESS, &data);
+        self.sock.request(msg)
+    }
+
+    pub fn addr_del(&mut self, index: u32, addr: IpAddr, prefix: u8) -> errno::Result<()> {
+        let mut msg = Message::new(RTM_DELADDR, 0);
+        let data = netlink::addr_bytes(addr);
+        msg.push(&ifaddrmsg(
+            family_of(addr),
+            prefix,
+            RT_SCOPE_UNIVERSE,
+            index,
+        ))
+        .attr(IFA_LOCAL, &data)
+        .attr(IFA_ADDRESS, &data);
+        self.sock.request(msg)
+    }
+
+    /// Adds a route to `dst/prefix`, through `gateway` if given, otherwise
+    /// directly on link `oif`.
+    pub fn route_add(
+        &mut self,
+        dst: IpAddr,
+        prefix: u8,
+        gateway: Option<IpAddr>,
+        oif: Option<u32>,
+    ) -> errno::Result<()> {
+        let scope = if gateway.is_some() {
+            RT_SCOPE_UNIVERSE
+        } else {
+            RT_SCOPE_LINK
+        };
+        let mut msg = Message::new(RTM_NEWROUTE, netlink::NLM_F_CREATE | netlink::NLM_F_EXCL);
+        msg.push(&rtmsg(family_of(dst), prefix, scope));
+        if prefix > 0 {
+            msg.attr(RTA_DST, &netlink::addr_bytes(dst));
+        }
+        if let Some(gw) = gateway {
+            msg.attr(RTA_GATEWAY, &netlink::addr_bytes(gw));
+        }
+        if let Some(index) = oif {
+            msg.attr_u32(RTA_OIF, index);
+        }
+        self.sock.request(msg)
+    }
+
+    /// The ID this namespace uses for `peer`, as shown in the
+    /// `link-netnsid` of links whose other end is there, if one has been
+    /// assigned.
+    pub fn nsid_get(&mut self, peer: &NetNS) -> errno::Result<Option<i32>> {
+        let mut msg = Message::new(RTM_GETNSID, 0);
+        msg.push(&rtgenmsg(libc::AF_UNSPEC as u8))
+            .attr_u32(NETNSA_FD, peer.fd() as u32);
+        Ok(parse_nsid(&self.sock.query(msg)?.payload))
+    }
+
+    /// Assigns `nsid` to `peer`, like `ip netns set`. Fails with `EEXIST`
+    /// if either is already assigned.
+    pub fn nsid_set(&mut self, peer: &NetNS, nsid: i32) -> errno::Result<()> {
+        let mut msg = Message::new(RTM_NEWNSID, 0);
+        msg.push(&rtgenmsg(libc::AF_UNSPEC as u8))
+            .attr_u32(NETNSA_FD, peer.fd() as u32)
+            .attr_u32(NETNSA_NSID, nsid as u32);
+        self.sock.request(msg)
+    }
+
+    /// Returns the ID of `peer`, letting the kernel pick a free one if it
+    /// has none yet.
+    pub fn nsid_assign(&mut self, peer: &NetNS) -> errno::Result<i32> {
+        if let Some(id) = self.nsid_get(peer)? {
+            return Ok(id);
+        }
+        match self.nsid_set(peer, NETNSA_NSID_NOT_ASSIGNED) {
+            Err(Error::Sys(e)) if e == Errno::from_i32(libc::EEXIST) => {}
+            res => res?,
+        }
+        self.nsid_get(peer)?
+            .ok_or(Error::Sys(Errno::from_i32(libc::ENOENT)))
+    }
+
+    /// All IDs assigned in this namespace, sorted.
+    pub fn nsids(&mut self) -> errno::Result<Vec<i32>> {
+        let mut msg = Message::new(RTM_GETNSID, netlink::NLM_F_DUMP);
+        msg.push(&rtgenmsg(libc::AF_UNSPEC as u8));
+        let mut ids: Vec<i32> = self
+            .sock
+            .dump(msg)?
+            .iter()
+            .filter_map(|r| parse_nsid(&r.payload))
+            .collect();
+        ids.sort_unstable();
+        Ok(ids)
+    }
+
+    /// Finds the namespace this namespace knows as `nsid` among those
+    /// returned by `NetNS::list_all`. Fails with `ENOENT` if none of them
+    /// has that ID.
+    pub fn nsid_resolve(&mut self, nsid: i32) -> errno::Result<NetNS> {
+        for info in NetNS::list_all()? {
+            let peer = match info.open() {
+                Ok(ns) => ns,
+                Err(_) => continue,
+            };
+            if self.nsid_get(&peer)? == Some(nsid) {
+                return Ok(peer);
+            }
+        }
+        Err(Error::Sys(Errno::from_i32(libc::ENOENT)))
+    }
+
+    pub fn route_add_default(&mut self, gateway: IpAddr) -> errno::Result<()> {
+        let any = match gateway {
+            IpAddr::V4(_) => IpAddr::from([0u8; 4]),
+            IpAddr::V6(_) => IpAddr::from([0u8; 16]),
+        };
+        self.route_add(any, 0, Some(gateway), None)
+    }
+}
@@ -0,0 +1,36 @@
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs, UdpSocket};
+use std::os::unix::io::{FromRawFd, OwnedFd};
+
+use super::errno::{self, Errno};
+use super::netns_linux::NetNS;
+
+// Sockets keep the network namespace they were created in for their whole
+// lifetime, so creating them on a thread that joined the namespace is
+// enough; the calling thread never switches. Addresses are resolved in the
+// calling thread before switching.
+impl NetNS {
+    /// Creates a raw socket inside this namespace, like socket(2).
+    /// `SOCK_CLOEXEC` is always added to `ty`.
+    pub fn socket(&self, domain: i32, ty: i32, proto: i32) -> errno::Result<OwnedFd> {
+        self.run(|| {
+            let fd = unsafe { libc::socket(domain, ty | libc::SOCK_CLOEXEC, proto) };
+            let fd = Errno::result(fd)?;
+            Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+        })
+    }
+
+    pub fn tcp_listener<A: ToSocketAddrs>(&self, addr: A) -> errno::Result<TcpListener> {
+        let addrs: Vec<SocketAddr> = addr.to_socket_addrs()?.collect();
+        self.run(|| Ok(TcpListener::bind(&addrs[..])?))
+    }
+
+    pub fn tcp_connect<A: ToSocketAddrs>(&self, addr: A) -> errno::Result<TcpStream> {
+        let addrs: Vec<SocketAddr> = addr.to_socket_addrs()?.collect();
+        self.run(|| Ok(TcpStream::connect(&addrs[..])?))
+    }
+
+    pub fn udp_socket<A: ToSocketAddrs>(&self, addr: A) -> errno::Result<UdpSocket> {
+        let addrs: Vec<SocketAddr> = addr.to_socket_addrs()?.collect();
+        self.run(|| Ok(UdpSocket::bind(&addrs[..])?))
+    }
+}
@@ -0,0 +1,177 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, OwnedFd};
+use std::os::unix::process::CommandExt as _;
+use std::process::{Command, ExitStatus};
+use std::thread;
+
+use super::errno::{self, Errno, Error};
+use super::namespace::{Namespace, NsType};
+use super::netns_linux::{
+    clone3, join_helper, pidfd_open, pidfd_send_signal, pipe, waitpid, NetNS,
+};
+
+/// A process started in fresh namespaces by `spawn_with_new_namespaces`.
+/// Like `std::process::Child`, dropping it neither kills nor reaps the
+/// process.
+#[derive(Debug)]
+pub struct NsChild {
+    pid: i32,
+    pidfd: OwnedFd,
+    netns: Option<NetNS>,
+    namespaces: Vec<Namespace>,
+    status: Option<ExitStatus>,
+}
+
+impl NsChild {
+    pub fn pid(&self) -> i32 {
+        self.pid
+    }
+
+    pub fn pidfd(&self) -> i32 {
+        self.pidfd.as_raw_fd()
+    }
+
+    /// The network namespace created for the child, if `CLONE_NEWNET` was
+    /// requested.
+    pub fn netns(&self) -> Option<&NetNS> {
+        self.netns.as_ref()
+    }
+
+    /// Handles to all namespaces created for the child. They stay valid
+    /// after the child exits.
+    pub fn namespaces(&self) -> &[Namespace] {
+        &self.namespaces
+    }
+
+    pub fn namespace(&self, ty: NsType) -> Option<&Namespace> {
+        self.namespaces.iter().find(|ns| ns.ns_type() == ty)
+    }
+
+    /// Sends `signal` through the pidfd, so it cannot hit a recycled pid.
+    pub fn kill(&self, signal: i32) -> errno::Result<()> {
+        pidfd_send_signal(self.pidfd.as_raw_fd(), signal)
+    }
+
+    pub fn wait(&mut self) -> errno::Result<ExitStatus> {
+        if let Some(status) = self.status {
+            return Ok(status);
+        }
+        let status = waitpid(self.pid)?;
+        self.status = Some(status);
+        Ok(status)
+    }
+}
+
+/// Starts `cmd` as a process created by clone3(2) with the `CLONE_NEW*`
+/// namespace `flags`, so it is born in the new namespaces rather than
+/// moved there; with `CLONE_NEWPID` it is PID 1 of its namespace.
+///
+/// std forks as usual and the forked process clones the real child with
+/// `CLONE_PARENT`, so the child is a direct child of the caller. It waits
+/// until the caller has opened its pidfd and namespaces and then execs
+/// with the stdio, environment and `pre_exec` hooks of `cmd`.
+pub fn spawn_with_new_namespaces(flags: i32, mut cmd: Command) -> errno::Result<NsChild> {
+    let types = NsType::from_flags(flags);
+    let known = types.iter().fold(0, |acc, ty| acc | ty.flag());
+    if flags != known {
+        return Err(Error::Sys(Errno::from_i32(libc::EINVAL)));
+    }
+
+    let (report_r, report_w) = pipe()?;
+    let (go_r, go_w) = pipe()?;
+    let fds = [
+        report_r.as_raw_fd(),
+        report_w.as_raw_fd(),
+        go_r.as_raw_fd(),
+        go_w.as_raw_fd(),
+    ];
+    let hook = move || {
+        // With CLONE_PARENT the exit signal is inherited from this
+        // process, which std started with SIGCHLD.
+        // The grandchild only reads a pipe before returning to std's
+        // pre-exec code, which is itself restricted to the same calls.
+        let pid = unsafe { clone3(flags | libc::CLONE_PARENT, 0)? };
+        if pid > 0 {
+            unsafe {
+                libc::write(fds[1], &pid as *const i32 as *const libc::c_void, 4);
+                libc::_exit(0);
+            }
+        }
+
+        // Only the read end of `go` is needed; closing the rest makes a
+        // vanished parent show up as EOF.
+        let mut ok = 0u8;
+        unsafe {
+            libc::close(fds[0]);
+            libc::close(fds[1]);
+            libc::close(fds[3]);
+            while libc::read(fds[2], &mut ok as *mut u8 as *mut libc::c_void, 1) < 0 {
+                if errno::errno() != libc::EINTR {
+                    break;
+                }
+            }
+        }
+        match ok {
+            1 => Ok(()),
+            _ => Err(io::Error::from_raw_os_error(libc::ECHILD)),
+        }
+    };
+    unsafe { cmd.pre_exec(hook) };
+
+    thread::scope(|s| {
+        let helper = s.spawn(move || prepare(report_r, go_w, &types));
+        let res = cmd.spawn();
+        drop(report_w);
+        drop(go_r);
+        let (pid, prepared) = join_helper(helper);
+
+        match res {
+            Ok(mut intermediate) => {
+                intermediate.wait()?;
+                let (pidfd, netns, namespaces) = prepared?;
+                Ok(NsChild {
+                    pid: pid.ok_or(Error::Sys(Errno::from_i32(libc::ECHILD)))?,
+                    pidfd,
+                    netns,
+                    namespaces,
+                    status: None,
+                })
+            }
+            Err(e) => {
+                if let Some(pid) = pid {
+                    let _ = waitpid(pid);
+                }
+                Err(e.into())
+            }
+        }
+    })
+}
+
+type Prepared = errno::Result<(OwnedFd, Option<NetNS>, Vec<Namespace>)>;
+
+// Runs in the caller while std is still waiting for the exec: learns the
+// pid of the real child, opens everything that has to be opened before it
+// can exit and then lets it continue.
+fn prepare(mut report: File, mut go: File, types: &[NsType]) -> (Option<i32>, Prepared) {
+    let mut buf = [0u8; 4];
+    if let Err(e) = report.read_exact(&mut buf) {
+        return (None, Err(e.into()));
+    }
+    let pid = i32::from_ne_bytes(buf);
+
+    let prepared = (|| {
+        let pidfd = pidfd_open(pid)?;
+        let mut namespaces = Vec::new();
+        for ty in types.iter() {
+            namespaces.push(Namespace::get_from_process(pid, *ty)?);
+        }
+        let netns = match types.contains(&NsType::Net) {
+            true => Some(NetNS::get_from_process(pid)?),
+            false => None,
+        };
+        Ok((pidfd, netns, namespaces))
+    })();
+    let _ = go.write_all(&[prepared.is_ok() as u8]);
+    (Some(pid), prepared)
+}
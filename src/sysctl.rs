@@ -0,0 +1,223 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::errno::{self, Errno, Error};
+use super::netns_linux::NetNS;
+
+const PROC_SYS: &str = "/proc/sys";
+
+/// A sysctl value, typed after the content of its `/proc/sys` file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SysctlValue {
+    Int(i64),
+    Ints(Vec<i64>),
+    Str(String),
+}
+
+impl SysctlValue {
+    pub fn parse(raw: &str) -> SysctlValue {
+        let fields: Vec<&str> = raw.split_whitespace().collect();
+        let ints: Result<Vec<i64>, _> = fields.iter().map(|f| f.parse::<i64>()).collect();
+        match ints {
+            Ok(ref v) if v.len() == 1 => SysctlValue::Int(v[0]),
+            Ok(v) if !v.is_empty() => SysctlValue::Ints(v),
+            _ => SysctlValue::Str(raw.trim_end_matches('\n').to_string()),
+        }
+    }
+
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            SysctlValue::Int(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        self.as_int().map(|v| v != 0)
+    }
+}
+
+impl fmt::Display for SysctlValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SysctlValue::Int(v) => write!(f, "{}", v),
+            SysctlValue::Ints(v) => {
+                let fields: Vec<String> = v.iter().map(|i| i.to_string()).collect();
+                write!(f, "{}", fields.join("\t"))
+            }
+            SysctlValue::Str(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+impl From<i64> for SysctlValue {
+    fn from(v: i64) -> SysctlValue {
+        SysctlValue::Int(v)
+    }
+}
+
+impl From<i32> for SysctlValue {
+    fn from(v: i32) -> SysctlValue {
+        SysctlValue::Int(v as i64)
+    }
+}
+
+impl From<bool> for SysctlValue {
+    fn from(v: bool) -> SysctlValue {
+        SysctlValue::Int(v as i64)
+    }
+}
+
+impl From<Vec<i64>> for SysctlValue {
+    fn from(v: Vec<i64>) -> SysctlValue {
+        SysctlValue::Ints(v)
+    }
+}
+
+impl From<&str> for SysctlValue {
+    fn from(v: &str) -> SysctlValue {
+        SysctlValue::Str(v.to_string())
+    }
+}
+
+impl From<String> for SysctlValue {
+    fn from(v: String) -> SysctlValue {
+        SysctlValue::Str(v)
+    }
+}
+
+/// Maps `net.ipv4.ip_forward` or `net/ipv4/ip_forward` to its file under
+/// `/proc/sys`. As with sysctl(8), `/` stands for a dot inside a component
+/// in the dotted form, e.g. `net.ipv4.conf.eth0/1.rp_filter`. Only `net.*`
+/// keys are accepted since those are the only ones scoped to a namespace.
+fn path_of(name: &str) -> errno::Result<PathBuf> {
+    let rel: String = match name.find(['.', '/']) {
+        Some(i) if name.as_bytes()[i] == b'.' => name
+            .chars()
+            .map(|c| match c {
+                '.' => '/',
+                '/' => '.',
+                c => c,
+            })
+            .collect(),
+        _ => name.trim_matches('/').to_string(),
+    };
+    let valid = rel
+        .split('/')
+        .all(|c| !c.is_empty() && c != "." && c != "..");
+    if !valid || !(rel == "net" || rel.starts_with("net/")) {
+        return Err(Error::Sys(Errno::from_i32(libc::EINVAL)));
+    }
+    Ok(Path::new(PROC_SYS).join(rel))
+}
+
+fn name_of(path: &Path) -> String {
+    let rel = path.strip_prefix(PROC_SYS).unwrap_or(path);
+    let parts: Vec<String> = rel
+        .iter()
+        .map(|c| c.to_string_lossy().replace('.', "/"))
+        .collect();
+    parts.join(".")
+}
+
+/// Reads a sysctl in the calling thread's network namespace.
+pub fn read(name: &str) -> errno::Result<SysctlValue> {
+    let raw = fs::read_to_string(path_of(name)?)?;
+    Ok(SysctlValue::parse(&raw))
+}
+
+/// Writes a sysctl in the calling thread's network namespace.
+pub fn write(name: &str, value: &SysctlValue) -> errno::Result<()> {
+    fs::write(path_of(name)?, value.to_string())?;
+    Ok(())
+}
+
+fn walk(dir: &Path, out: &mut Vec<(String, SysctlValue)>) -> errno::Result<()> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .collect();
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            walk(&path, out)?;
+        } else if let Ok(raw) = fs::read_to_string(&path) {
+            // Write-only entries such as route/flush cannot be read and
+            // are skipped.
+            out.push((name_of(&path), SysctlValue::parse(&raw)));
+        }
+    }
+    Ok(())
+}
+
+/// Lists every readable sysctl below `prefix` in the calling thread's
+/// network namespace.
+pub fn list(prefix: &str) -> errno::Result<Vec<(String, SysctlValue)>> {
+    let path = path_of(prefix)?;
+    let mut out = Vec::new();
+    if path.is_dir() {
+        walk(&path, &mut out)?;
+    } else {
+        out.push((
+            name_of(&path),
+            SysctlValue::parse(&fs::read_to_string(&path)?),
+        ));
+    }
+    Ok(out)
+}
+
+impl NetNS {
+    pub fn sysctl_get(&self, name: &str) -> errno::Result<SysctlValue> {
+        self.run(|| read(name))
+    }
+
+    pub fn sysctl_set<V: Into<SysctlValue>>(&self, name: &str, value: V) -> errno::Result<()> {
+        let value = value.into();
+        self.run(|| write(name, &value))
+    }
+
+    pub fn sysctl_list(&self, prefix: &str) -> errno::Result<Vec<(String, SysctlValue)>> {
+        self.run(|| list(prefix))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_values() {
+        assert_eq!(SysctlValue::parse("1\n"), SysctlValue::Int(1));
+        assert_eq!(
+            SysctlValue::parse("0\t2147483647\n"),
+            SysctlValue::Ints(vec![0, 2147483647])
+        );
+        assert_eq!(
+            SysctlValue::parse("cubic reno\n"),
+            SysctlValue::Str("cubic reno".to_string())
+        );
+        assert_eq!(SysctlValue::parse(""), SysctlValue::Str(String::new()));
+    }
+
+    #[test]
+    fn names_and_paths() {
+        assert_eq!(
+            path_of("net.ipv4.ip_forward").unwrap(),
+            Path::new("/proc/sys/net/ipv4/ip_forward")
+        );
+        assert_eq!(
+            path_of("net/ipv4/conf/eth0.1/rp_filter").unwrap(),
+            Path::new("/proc/sys/net/ipv4/conf/eth0.1/rp_filter")
+        );
+        assert!(path_of("kernel.hostname").is_err());
+        assert_eq!(
+            path_of("net.ipv4.conf.eth0/1.rp_filter").unwrap(),
+            Path::new("/proc/sys/net/ipv4/conf/eth0.1/rp_filter")
+        );
+        assert!(path_of("net/../kernel/hostname").is_err());
+        assert_eq!(
+            name_of(Path::new("/proc/sys/net/ipv4/conf/eth0.1/rp_filter")),
+            "net.ipv4.conf.eth0/1.rp_filter"
+        );
+    }
+}
@@ -0,0 +1,100 @@
+use std::fs;
+use std::time::Duration;
+
+use super::errno;
+use super::namespace::{Namespace, NsType};
+use super::netns_linux::{gettid, on_helper_thread};
+
+/// The clocks a time namespace can shift.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Clock {
+    Monotonic,
+    Boottime,
+}
+
+impl Clock {
+    fn name(self) -> &'static str {
+        match self {
+            Clock::Monotonic => "monotonic",
+            Clock::Boottime => "boottime",
+        }
+    }
+}
+
+/// How far a clock inside a time namespace is from the same clock outside,
+/// as `secs` plus `nanos` (always positive), like in
+/// `/proc/<pid>/timens_offsets`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ClockOffset {
+    pub clock: Clock,
+    pub secs: i64,
+    pub nanos: u32,
+}
+
+impl ClockOffset {
+    /// `clock` runs `d` ahead inside the namespace.
+    pub fn ahead(clock: Clock, d: Duration) -> ClockOffset {
+        ClockOffset {
+            clock,
+            secs: d.as_secs() as i64,
+            nanos: d.subsec_nanos(),
+        }
+    }
+
+    /// `clock` runs `d` behind inside the namespace. The kernel rejects
+    /// offsets that would make the clock negative.
+    pub fn behind(clock: Clock, d: Duration) -> ClockOffset {
+        let (secs, nanos) = match d.subsec_nanos() {
+            0 => (-(d.as_secs() as i64), 0),
+            n => (-(d.as_secs() as i64) - 1, 1_000_000_000 - n),
+        };
+        ClockOffset { clock, secs, nanos }
+    }
+}
+
+fn format_offsets(offsets: &[ClockOffset]) -> String {
+    offsets
+        .iter()
+        .map(|o| format!("{} {} {}\n", o.clock.name(), o.secs, o.nanos))
+        .collect()
+}
+
+impl Namespace {
+    /// Creates a time namespace with the given clock offsets without
+    /// entering it. Offsets can only be set before the
+    /// first process enters the namespace, so they are written right
+    /// after creating it. Processes join it through
+    /// `CommandExt::namespaces`.
+    pub fn new_time(offsets: &[ClockOffset]) -> errno::Result<Namespace> {
+        on_helper_thread(|| {
+            let ns = Namespace::new(NsType::Time)?;
+            // There is no per-thread offsets file under `task/`, but
+            // `/proc/<tid>` refers to the thread and thereby to the
+            // namespace its children would be created in.
+            fs::write(
+                format!("/proc/{}/timens_offsets", gettid()),
+                format_offsets(offsets),
+            )?;
+            Ok(ns)
+        })
+        .map(Namespace::into_fd_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offsets() {
+        let d = Duration::new(5, 250_000_000);
+        assert_eq!(
+            format_offsets(&[
+                ClockOffset::ahead(Clock::Monotonic, d),
+                ClockOffset::behind(Clock::Boottime, d),
+                ClockOffset::behind(Clock::Boottime, Duration::from_secs(3)),
+            ]),
+            "monotonic 5 250000000\nboottime -6 750000000\nboottime -3 0\n"
+        );
+    }
+}
@@ -0,0 +1,188 @@
+use std::fs;
+use std::io::{self, Read};
+use std::os::unix::io::AsRawFd;
+
+use super::errno::{self, Errno, Error};
+use super::namespace::{Namespace, NsType};
+use super::netns_linux::{
+    clone3, getpid, pipe, setns, unshare, waitpid, NetNS, CLONE_NEWNET, CLONE_NEWUSER,
+};
+
+/// One line of a `uid_map` or `gid_map`: `count` ids starting at `outside`
+/// in the parent namespace appear as ids starting at `inside`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct IdMap {
+    pub inside: u32,
+    pub outside: u32,
+    pub count: u32,
+}
+
+impl IdMap {
+    pub fn new(inside: u32, outside: u32, count: u32) -> IdMap {
+        IdMap {
+            inside,
+            outside,
+            count,
+        }
+    }
+
+    /// Maps root in the new namespace to the caller's effective uid, the
+    /// only mapping an unprivileged process may write.
+    pub fn current_user() -> IdMap {
+        IdMap::new(0, unsafe { libc::geteuid() }, 1)
+    }
+
+    /// Like `current_user` for the effective gid.
+    pub fn current_group() -> IdMap {
+        IdMap::new(0, unsafe { libc::getegid() }, 1)
+    }
+}
+
+fn format_map(map: &[IdMap]) -> String {
+    map.iter()
+        .map(|m| format!("{} {} {}\n", m.inside, m.outside, m.count))
+        .collect()
+}
+
+// gid_map can only be written without CAP_SETGID once setgroups(2) has been
+// disabled for the namespace, so that is done for unprivileged callers.
+// Whether the caller is privileged has to be decided before unsharing, as
+// the uid changes once the maps are written.
+fn write_maps(
+    pid: &str,
+    uid_map: &[IdMap],
+    gid_map: &[IdMap],
+    privileged: bool,
+) -> errno::Result<()> {
+    if !uid_map.is_empty() {
+        fs::write(format!("/proc/{}/uid_map", pid), format_map(uid_map))?;
+    }
+    if !gid_map.is_empty() {
+        if !privileged {
+            match fs::write(format!("/proc/{}/setgroups", pid), "deny") {
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+                res => res?,
+            }
+        }
+        fs::write(format!("/proc/{}/gid_map", pid), format_map(gid_map))?;
+    }
+    Ok(())
+}
+
+// Runs `setup` in a short-lived child created with the clone `flags`, then
+// `f` in the caller with the child's pid while the child is kept alive.
+// `setup` runs in a forked copy of a possibly multithreaded process and
+// must only make async-signal-safe calls.
+fn with_helper<S, F, T>(flags: i32, setup: S, f: F) -> errno::Result<T>
+where
+    S: FnOnce() -> errno::Result<()>,
+    F: FnOnce(i32) -> errno::Result<T>,
+{
+    let (mut ready_r, ready_w) = pipe()?;
+    let (go_r, go_w) = pipe()?;
+
+    // `setup` only makes plain system calls, and the child leaves with
+    // `_exit`.
+    let pid = unsafe { clone3(flags, libc::SIGCHLD)? };
+    if pid == 0 {
+        let code: i32 = match setup() {
+            Ok(()) => 0,
+            Err(e) => io::Error::from(e).raw_os_error().unwrap_or(libc::EIO),
+        };
+        let mut buf = 0u8;
+        unsafe {
+            libc::write(
+                ready_w.as_raw_fd(),
+                &code as *const i32 as *const libc::c_void,
+                4,
+            );
+            libc::close(go_w.as_raw_fd());
+            libc::read(
+                go_r.as_raw_fd(),
+                &mut buf as *mut u8 as *mut libc::c_void,
+                1,
+            );
+            libc::_exit(0);
+        }
+    }
+
+    drop(ready_w);
+    drop(go_r);
+    let mut buf = [0u8; 4];
+    let res = match ready_r.read_exact(&mut buf) {
+        Ok(()) => match i32::from_ne_bytes(buf) {
+            0 => f(pid),
+            code => Err(Error::Sys(Errno::from_i32(code))),
+        },
+        Err(e) => Err(e.into()),
+    };
+    drop(go_w);
+    waitpid(pid)?;
+    res
+}
+
+/// A user namespace. Network namespaces created while in it, or through
+/// `new_netns`, are owned by it, so a process with no privileges outside
+/// can administer them once it has joined the user namespace.
+#[derive(Debug)]
+pub struct UserNS {
+    ns: Namespace,
+}
+
+impl UserNS {
+    /// Moves the calling process into a new user namespace with the given
+    /// id maps, like `unshare --user --map-root-user`. The kernel only
+    /// allows this while the process has a single thread; afterwards
+    /// `NetNS::new` and friends work without privileges.
+    pub fn new(uid_map: &[IdMap], gid_map: &[IdMap]) -> errno::Result<UserNS> {
+        let privileged = unsafe { libc::geteuid() } == 0;
+        unshare(CLONE_NEWUSER)?;
+        write_maps("self", uid_map, gid_map, privileged)?;
+        UserNS::get()
+    }
+
+    /// Creates a user namespace without joining it. A helper process is
+    /// started in the namespace to write the id maps and exits again.
+    pub fn new_detached(uid_map: &[IdMap], gid_map: &[IdMap]) -> errno::Result<UserNS> {
+        let privileged = unsafe { libc::geteuid() } == 0;
+        with_helper(
+            CLONE_NEWUSER,
+            || Ok(()),
+            |pid| {
+                write_maps(&pid.to_string(), uid_map, gid_map, privileged)?;
+                UserNS::get_from_process(pid)
+            },
+        )
+    }
+
+    pub fn get() -> errno::Result<UserNS> {
+        UserNS::get_from_process(getpid())
+    }
+
+    pub fn get_from_process(pid: i32) -> errno::Result<UserNS> {
+        Ok(UserNS {
+            ns: Namespace::get_from_process(pid, NsType::User)?,
+        })
+    }
+
+    pub fn namespace(&self) -> &Namespace {
+        &self.ns
+    }
+
+    /// Creates a network namespace owned by this user namespace, from a
+    /// helper process that joins it. `lo` is left down, like `NetNS::new`.
+    /// Combined with `CommandExt::namespaces` this runs commands in
+    /// the new namespace without privileges.
+    pub fn new_netns(&self) -> errno::Result<NetNS> {
+        let fd = self.ns.fd();
+        with_helper(
+            0,
+            || {
+                setns(fd, CLONE_NEWUSER)?;
+                unshare(CLONE_NEWNET)?;
+                Ok(())
+            },
+            NetNS::get_from_process,
+        )
+    }
+}
@@ -0,0 +1,100 @@
+use std::ffi::CStr;
+
+use super::errno::{self, Errno};
+use super::namespace::{Namespace, NsType};
+use super::netns_linux::{getpid, gettid, unshare, CLONE_NEWUTS};
+
+/// A UTS namespace, holding a hostname and NIS domain name. As with
+/// `NetNS`, `new` and `set` only affect the calling thread.
+#[derive(Debug)]
+pub struct UtsNS {
+    ns: Namespace,
+}
+
+impl UtsNS {
+    /// Moves the calling thread into a new UTS namespace, starting with
+    /// the names of the current one.
+    pub fn new() -> errno::Result<UtsNS> {
+        unshare(CLONE_NEWUTS)?;
+        UtsNS::get()
+    }
+
+    /// Creates a UTS namespace with the given hostname. The calling thread
+    /// keeps its own names.
+    pub fn new_detached(hostname: &str) -> errno::Result<UtsNS> {
+        let ns = Namespace::new_detached(NsType::Uts)?;
+        ns.run(|| set_hostname(hostname))?;
+        Ok(UtsNS { ns })
+    }
+
+    pub fn get() -> errno::Result<UtsNS> {
+        Ok(UtsNS {
+            ns: Namespace::get_from_thread(getpid(), gettid(), NsType::Uts)?,
+        })
+    }
+
+    pub fn get_from_process(pid: i32) -> errno::Result<UtsNS> {
+        Ok(UtsNS {
+            ns: Namespace::get_from_process(pid, NsType::Uts)?,
+        })
+    }
+
+    pub fn set(ns: &UtsNS) -> errno::Result<()> {
+        Namespace::set(&ns.ns)
+    }
+
+    pub fn namespace(&self) -> &Namespace {
+        &self.ns
+    }
+
+    pub fn hostname(&self) -> errno::Result<String> {
+        self.ns.run(hostname)
+    }
+
+    pub fn set_hostname(&self, name: &str) -> errno::Result<()> {
+        self.ns.run(|| set_hostname(name))
+    }
+
+    pub fn domainname(&self) -> errno::Result<String> {
+        self.ns.run(domainname)
+    }
+
+    pub fn set_domainname(&self, name: &str) -> errno::Result<()> {
+        self.ns.run(|| set_domainname(name))
+    }
+}
+
+// The functions below act on the calling thread's UTS namespace.
+
+fn uname() -> errno::Result<libc::utsname> {
+    let mut buf: libc::utsname = unsafe { std::mem::zeroed() };
+    let res = unsafe { libc::uname(&mut buf) };
+    Errno::result(res)?;
+    Ok(buf)
+}
+
+fn field(f: &[libc::c_char]) -> String {
+    unsafe { CStr::from_ptr(f.as_ptr()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+pub fn hostname() -> errno::Result<String> {
+    Ok(field(&uname()?.nodename))
+}
+
+pub fn set_hostname(name: &str) -> errno::Result<()> {
+    let res = unsafe { libc::sethostname(name.as_ptr() as *const libc::c_char, name.len()) };
+    Errno::result(res)?;
+    Ok(())
+}
+
+pub fn domainname() -> errno::Result<String> {
+    Ok(field(&uname()?.domainname))
+}
+
+pub fn set_domainname(name: &str) -> errno::Result<()> {
+    let res = unsafe { libc::setdomainname(name.as_ptr() as *const libc::c_char, name.len()) };
+    Errno::result(res)?;
+    Ok(())
+}
@@ -0,0 +1,262 @@
+use std::collections::{BTreeSet, HashSet, VecDeque};
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io::Read;
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+
+use super::errno::{self, Errno, Error};
+use super::monitor::Group;
+use super::netlink::{self, Socket};
+use super::netns_linux::{NetNS, NETNS_RUN_DIR};
+
+const NSFS_MAGIC: i64 = 0x6e736673;
+
+// How often names that exist but are not bind mounted yet are checked
+// again if no mount or NSID event arrives, e.g. because mounts in
+// `/run/netns` do not propagate to the caller's mount namespace.
+const PENDING_RETRY_MS: i32 = 100;
+
+const WATCH_MASK: u32 = libc::IN_CREATE
+    | libc::IN_DELETE
+    | libc::IN_MOVED_TO
+    | libc::IN_MOVED_FROM
+    | libc::IN_DELETE_SELF;
+
+/// A change to the named network namespaces in `/run/netns`.
+#[derive(Debug)]
+pub enum NetnsEvent {
+    Created(String, NetNS),
+    Deleted(String),
+}
+
+/// Watches `/run/netns` for namespaces added and removed with
+/// `ip netns add` and `ip netns delete` or equivalent tools. Iterating
+/// blocks until the next change.
+///
+/// `ip netns add` creates the file before it bind mounts the namespace
+/// onto it, so a new name is only reported once the mount shows up, which
+/// the watcher learns from mount table changes and netlink NSID events.
+/// Names that disappear before that are not reported at all.
+#[derive(Debug)]
+pub struct NetnsWatcher {
+    inotify: File,
+    mounts: File,
+    nsid: Socket,
+    known: HashSet<String>,
+    pending: BTreeSet<String>,
+    queue: VecDeque<NetnsEvent>,
+}
+
+impl NetnsWatcher {
+    /// Starts watching, creating `/run/netns` if needed. Namespaces that
+    /// already exist are reported as `Created` first, so none are missed
+    /// between listing the directory and watching it.
+    pub fn new() -> errno::Result<NetnsWatcher> {
+        fs::create_dir_all(NETNS_RUN_DIR)?;
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        let inotify = unsafe { File::from_raw_fd(Errno::result(fd)?) };
+        let dir = CString::new(NETNS_RUN_DIR).unwrap();
+        let res = unsafe { libc::inotify_add_watch(inotify.as_raw_fd(), dir.as_ptr(), WATCH_MASK) };
+        Errno::result(res)?;
+
+        let mut nsid = Socket::new(netlink::NETLINK_ROUTE)?;
+        nsid.add_membership(Group::NsId.number())?;
+
+        let mut watcher = NetnsWatcher {
+            inotify,
+            mounts: File::open("/proc/self/mountinfo")?,
+            nsid,
+            known: HashSet::new(),
+            pending: BTreeSet::new(),
+            queue: VecDeque::new(),
+        };
+        watcher.rescan()?;
+        Ok(watcher)
+    }
+
+    // Brings `known` in line with the directory, after starting or when
+    // inotify events were lost.
+    fn rescan(&mut self) -> errno::Result<()> {
+        let mut names = HashSet::new();
+        for entry in fs::read_dir(NETNS_RUN_DIR)?.flatten() {
+            if let Ok(name) = entry.file_name().into_string() {
+                names.insert(name);
+            }
+        }
+        let gone: Vec<String> = self.known.difference(&names).cloned().collect();
+        for name in gone {
+            self.known.remove(&name);
+            self.queue.push_back(NetnsEvent::Deleted(name));
+        }
+        self.pending = names.difference(&self.known).cloned().collect();
+        self.check_pending();
+        Ok(())
+    }
+
+    fn check_pending(&mut self) {
+        let pending: Vec<String> = self.pending.iter().cloned().collect();
+        for name in pending {
+            match open_mounted(&name) {
+                Ok(Some(ns)) => {
+                    self.pending.remove(&name);
+                    self.known.insert(name.clone());
+                    self.queue.push_back(NetnsEvent::Created(name, ns));
+                }
+                Ok(None) => {}
+                Err(_) => {
+                    self.pending.remove(&name);
+                }
+            }
+        }
+    }
+
+    fn handle(&mut self, mask: u32, name: String) -> errno::Result<()> {
+        if mask & libc::IN_Q_OVERFLOW != 0 {
+            return self.rescan();
+        }
+        if mask & libc::IN_DELETE_SELF != 0 {
+            return Err(Error::Sys(Errno::from_i32(libc::ENOENT)));
+        }
+        if mask & (libc::IN_CREATE | libc::IN_MOVED_TO) != 0 {
+            self.pending.insert(name);
+        } else if mask & (libc::IN_DELETE | libc::IN_MOVED_FROM) != 0 {
+            self.pending.remove(&name);
+            if self.known.remove(&name) {
+                self.queue.push_back(NetnsEvent::Deleted(name));
+            }
+        }
+        Ok(())
+    }
+
+    fn wait(&mut self) -> errno::Result<()> {
+        let mut fds = [
+            libc::pollfd {
+                fd: self.inotify.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: self.mounts.as_raw_fd(),
+                events: libc::POLLPRI,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: self.nsid.fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+        let timeout = if self.pending.is_empty() {
+            -1
+        } else {
+            PENDING_RETRY_MS
+        };
+        let res = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) };
+        if res < 0 {
+            let e = Errno::last();
+            if e == Errno::from_i32(libc::EINTR) {
+                return Ok(());
+            }
+            return Err(Error::Sys(e));
+        }
+
+        if fds[0].revents != 0 {
+            let mut buf = [0u8; 4096];
+            let n = self.inotify.read(&mut buf)?;
+            for (mask, name) in inotify_events(&buf[..n]) {
+                self.handle(mask, name)?;
+            }
+        }
+        if fds[2].revents != 0 {
+            // Only the wakeup matters, the pending names are checked below.
+            while let Ok(replies) = self.nsid.recv(libc::MSG_DONTWAIT) {
+                if replies.is_empty() {
+                    break;
+                }
+            }
+        }
+        self.check_pending();
+        Ok(())
+    }
+}
+
+impl Iterator for NetnsWatcher {
+    type Item = errno::Result<NetnsEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.queue.pop_front() {
+                return Some(Ok(event));
+            }
+            if let Err(e) = self.wait() {
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+// Opens `/run/netns/<name>` if a namespace is mounted there yet.
+fn open_mounted(name: &str) -> errno::Result<Option<NetNS>> {
+    let ns = NetNS::get_from_path(&format!("{}/{}", NETNS_RUN_DIR, name))?;
+    let mut st: libc::statfs = unsafe { mem::zeroed() };
+    Errno::result(unsafe { libc::fstatfs(ns.fd(), &mut st) })?;
+    if st.f_type as i64 == NSFS_MAGIC {
+        Ok(Some(ns))
+    } else {
+        Ok(None)
+    }
+}
+
+// Splits what was read from an inotify descriptor into the mask and name
+// of each `struct inotify_event`.
+fn inotify_events(buf: &[u8]) -> Vec<(u32, String)> {
+    const HDR: usize = 16;
+    let mut out = Vec::new();
+    let mut off = 0;
+    while off + HDR <= buf.len() {
+        let field = |i: usize| {
+            let b = &buf[off + i..off + i + 4];
+            u32::from_ne_bytes([b[0], b[1], b[2], b[3]])
+        };
+        let (mask, len) = (field(4), field(12) as usize);
+        let end = (off + HDR + len).min(buf.len());
+        let name = &buf[off + HDR..end];
+        let name = name.split(|&c| c == 0).next().unwrap_or(&[]);
+        out.push((mask, String::from_utf8_lossy(name).into_owned()));
+        off = end;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(mask: u32, name: &str) -> Vec<u8> {
+        let len = netlink::align(name.len() + 1);
+        let mut b = Vec::new();
+        b.extend_from_slice(&1i32.to_ne_bytes());
+        b.extend_from_slice(&mask.to_ne_bytes());
+        b.extend_from_slice(&0u32.to_ne_bytes());
+        b.extend_from_slice(&(len as u32).to_ne_bytes());
+        b.extend_from_slice(name.as_bytes());
+        b.resize(16 + len, 0);
+        b
+    }
+
+    #[test]
+    fn inotify() {
+        let mut buf = event(libc::IN_CREATE, "blue");
+        buf.extend(event(libc::IN_DELETE, "red-ns"));
+        buf.extend(event(libc::IN_Q_OVERFLOW, ""));
+        assert_eq!(
+            inotify_events(&buf),
+            vec![
+                (libc::IN_CREATE, "blue".to_string()),
+                (libc::IN_DELETE, "red-ns".to_string()),
+                (libc::IN_Q_OVERFLOW, String::new()),
+            ]
+        );
+    }
+}